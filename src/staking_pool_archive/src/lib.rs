@@ -0,0 +1,131 @@
+// Archive for the staking pool's ICRC-3 block log. Created and controlled by the pool
+// canister, which moves its oldest blocks here once its local log grows past a
+// threshold. Blocks are stored as-is, keyed by their index in the pool's log.
+use candid::{candid_method, CandidType, Decode, Deserialize, Encode, Int, Nat, Principal};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const MAX_BLOCKS_PER_RESPONSE: u64 = 100;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Storable for Value {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode block"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Value).expect("Failed to decode block")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+candid::define_function!(pub GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksCallback,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // The pool canister that created this archive; the only principal allowed to append
+    static POOL: RefCell<StableCell<Principal, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), Principal::anonymous())
+            .expect("Failed to initialize the pool cell")
+    );
+
+    static BLOCKS: RefCell<StableBTreeMap<u64, Value, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))))
+    );
+}
+
+#[ic_cdk::init]
+fn init() {
+    let pool = ic_cdk::caller();
+    POOL.with(|p| p.borrow_mut().set(pool)).expect("Failed to store the pool canister id");
+}
+
+#[ic_cdk::update]
+#[candid_method(update)]
+fn append_blocks(blocks: Vec<BlockWithId>) -> Result<(), String> {
+    if ic_cdk::caller() != POOL.with(|p| *p.borrow().get()) {
+        return Err("Only the pool canister can append blocks".to_string());
+    }
+
+    BLOCKS.with(|b| {
+        let mut stored = b.borrow_mut();
+        for block in blocks {
+            let id = u64::try_from(&block.id.0).map_err(|_| "Block id out of range".to_string())?;
+            stored.insert(id, block.block);
+        }
+        Ok(())
+    })
+}
+
+// Serves the blocks this archive holds; ranges outside it are simply not returned
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    BLOCKS.with(|b| {
+        let stored = b.borrow();
+        let mut blocks = Vec::new();
+        for arg in args {
+            let start = u64::try_from(&arg.start.0).unwrap_or(u64::MAX);
+            let length = u64::try_from(&arg.length.0).unwrap_or(u64::MAX);
+            let remaining = MAX_BLOCKS_PER_RESPONSE.saturating_sub(blocks.len() as u64);
+            let end = start.saturating_add(length.min(remaining));
+            blocks.extend(stored.range(start..end).map(|(id, block)| BlockWithId { id: Nat::from(id), block }));
+        }
+
+        GetBlocksResult {
+            log_length: Nat::from(stored.last_key_value().map_or(0, |(id, _)| id + 1)),
+            blocks,
+            archived_blocks: Vec::new(),
+        }
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_block_count() -> u64 {
+    BLOCKS.with(|b| b.borrow().len())
+}
+
+ic_cdk::export_candid!();
@@ -0,0 +1,105 @@
+// Proportional allocation by the largest-remainder method. Each key first gets the
+// floor of its exact share; the units lost to rounding then go one each to the keys
+// with the largest remainders, ties going to the lower key. Allocations sum to exactly
+// `amount` whenever some weight is non-zero, and depend only on the inputs.
+use std::collections::BTreeMap;
+
+// If `amount` times the total weight would overflow, weights are halved until it
+// fits. That only happens far beyond any real token supply.
+pub(crate) fn allocate(amount: u64, weights: &BTreeMap<u64, u128>) -> BTreeMap<u64, u64> {
+    let mut shift = 0;
+    let (scaled, total) = loop {
+        let scaled: Vec<(u64, u128)> = weights.iter().map(|(key, weight)| (*key, weight >> shift)).collect();
+        let total = scaled.iter().try_fold(0u128, |sum, (_, weight)| sum.checked_add(*weight));
+        if let Some(total) = total.filter(|total| total.checked_mul(amount as u128).is_some()) {
+            break (scaled, total);
+        }
+        shift += 1;
+    };
+
+    if total == 0 {
+        return weights.keys().map(|key| (*key, 0)).collect();
+    }
+
+    let mut allocations = BTreeMap::new();
+    let mut remainders = Vec::with_capacity(scaled.len());
+    let mut assigned = 0u64;
+    for (key, weight) in scaled {
+        let exact = weight * amount as u128;
+        let share = (exact / total) as u64;
+        assigned += share;
+        allocations.insert(key, share);
+        remainders.push((exact % total, key));
+    }
+
+    // Fewer leftover units than keys with a non-zero remainder, so each gets at most one
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let leftover = amount - assigned;
+    for (_, key) in remainders.into_iter().take(leftover as usize) {
+        *allocations.entry(key).or_default() += 1;
+    }
+    allocations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Token amounts times tier multipliers, as `reward_pool` builds them
+    fn weights() -> impl Strategy<Value = BTreeMap<u64, u128>> {
+        prop::collection::btree_map(any::<u64>(), 0u128..=(u64::MAX as u128) * 30_000, 0..50)
+    }
+
+    proptest! {
+        #[test]
+        fn sums_to_amount(amount in any::<u64>(), weights in weights()) {
+            let allocations = allocate(amount, &weights);
+            let sum: u128 = allocations.values().map(|a| *a as u128).sum();
+            if weights.values().any(|w| *w > 0) {
+                prop_assert_eq!(sum, amount as u128);
+            } else {
+                prop_assert_eq!(sum, 0);
+            }
+        }
+
+        #[test]
+        fn allocates_every_key_once(amount in any::<u64>(), weights in weights()) {
+            let allocations = allocate(amount, &weights);
+            prop_assert!(allocations.keys().eq(weights.keys()));
+        }
+
+        #[test]
+        fn within_one_of_exact_share(amount in 0u64..1_000_000_000_000, weights in weights()) {
+            let total: u128 = weights.values().sum();
+            prop_assume!(total > 0);
+            let allocations = allocate(amount, &weights);
+            for (key, weight) in &weights {
+                let floor = (weight * amount as u128 / total) as u64;
+                let allocation = allocations[key];
+                prop_assert!(allocation == floor || allocation == floor + 1);
+                if *weight == 0 {
+                    prop_assert_eq!(allocation, 0);
+                }
+            }
+        }
+
+        #[test]
+        fn larger_weight_never_gets_less(amount in 0u64..1_000_000_000_000, weights in weights()) {
+            let allocations = allocate(amount, &weights);
+            for (a, weight_a) in &weights {
+                for (b, weight_b) in &weights {
+                    if weight_a > weight_b {
+                        prop_assert!(allocations[a] >= allocations[b]);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn unchanged_by_scaling_weights(amount in 0u64..1_000_000_000, weights in weights(), factor in 1u128..1000) {
+            let scaled: BTreeMap<u64, u128> = weights.iter().map(|(k, w)| (*k, w * factor)).collect();
+            prop_assert_eq!(allocate(amount, &weights), allocate(amount, &scaled));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Certified data. The canister's certified data is the root hash of this tree:
+//
+//   last_block_hash, last_block_index        ICRC-3 tip, see `icrc3`
+//   staking/positions/<principal>/<id>       one leaf per deposit
+//   staking/total_staked                     big-endian u64
+//
+// Deposit ids in the path are big-endian u64. A deposit leaf is the big-endian amount,
+// deposit time and lock period, the 32-byte subaccount, the big-endian accrued rewards
+// and one byte that is 1 if the position auto-compounds. Certified queries
+// return the system certificate with a CBOR-encoded witness for the requested path,
+// so frontends can verify responses without trusting the replica that answered.
+use candid::{candid_method, Principal};
+use ic_certification::{
+    fork, fork_hash, labeled, labeled_hash, leaf, leaf_hash, pruned, AsHashTree, Hash, HashTree, RbTree,
+};
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+
+use crate::types::*;
+use crate::{icrc3, State, STATE};
+
+// Deposit leaves keyed by deposit id, per principal
+type Positions = RbTree<Vec<u8>, RbTree<Vec<u8>, Vec<u8>>>;
+
+thread_local! {
+    static POSITIONS: RefCell<Positions> = const { RefCell::new(RbTree::new()) };
+    static TOTAL_STAKED: Cell<u64> = const { Cell::new(0) };
+}
+
+fn deposit_leaf(deposit: &Deposit) -> Vec<u8> {
+    [
+        deposit.amount.to_be_bytes().as_slice(),
+        &deposit.deposit_time.to_be_bytes(),
+        &deposit.lock_period.to_be_bytes(),
+        &deposit.subaccount.0,
+        &deposit.rewards.to_be_bytes(),
+        &[deposit.auto_compound as u8],
+    ].concat()
+}
+
+fn tip_hash() -> Hash {
+    icrc3::tip_tree().unwrap_or_else(ic_certification::empty).digest()
+}
+
+fn positions_labeled_hash() -> Hash {
+    POSITIONS.with(|p| labeled_hash(b"positions", &p.borrow().root_hash()))
+}
+
+fn total_staked_tree() -> HashTree {
+    labeled("total_staked", leaf(TOTAL_STAKED.with(|t| t.get()).to_be_bytes().to_vec()))
+}
+
+fn staking_hash() -> Hash {
+    let total_hash = labeled_hash(b"total_staked", &leaf_hash(&TOTAL_STAKED.with(|t| t.get()).to_be_bytes()));
+    labeled_hash(b"staking", &fork_hash(&positions_labeled_hash(), &total_hash))
+}
+
+// Recompute the root hash and hand it to the system
+pub(crate) fn certify() {
+    ic_cdk::api::set_certified_data(&fork_hash(&tip_hash(), &staking_hash()));
+}
+
+// Refresh the certified positions of `user` and the certified total from `state`
+pub(crate) fn certify_user(state: &State, user: &Principal) {
+    let deposits = state.get_user_deposits(user).map(|ud| ud.deposits.as_slice()).unwrap_or_default();
+    POSITIONS.with(|p| {
+        let mut positions = p.borrow_mut();
+        let key = user.as_slice().to_vec();
+        if deposits.is_empty() {
+            positions.delete(&key);
+        } else {
+            let mut tree = RbTree::new();
+            for deposit in deposits {
+                tree.insert(deposit.id.to_be_bytes().to_vec(), deposit_leaf(deposit));
+            }
+            positions.insert(key, tree);
+        }
+    });
+    certify_total(state);
+}
+
+pub(crate) fn certify_total(state: &State) {
+    TOTAL_STAKED.with(|t| t.set(state.total_staked));
+    certify();
+}
+
+pub(crate) fn encode_witness(tree: &HashTree) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut serializer = serde_cbor::Serializer::new(&mut bytes);
+    serializer.self_describe().ok()?;
+    tree.serialize(&mut serializer).ok()?;
+    Some(bytes)
+}
+
+// Witness for the ICRC-3 tip, with the staking branch pruned
+pub(crate) fn tip_witness() -> Option<HashTree> {
+    Some(fork(icrc3::tip_tree()?, pruned(staking_hash())))
+}
+
+// Only available in query calls, where the system provides a certificate
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_certified_total_staked() -> Option<CertifiedTotalStaked> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let witness = fork(
+        pruned(tip_hash()),
+        labeled("staking", fork(pruned(positions_labeled_hash()), total_staked_tree())),
+    );
+    Some(CertifiedTotalStaked {
+        total_staked: TOTAL_STAKED.with(|t| t.get()),
+        certificate,
+        witness: encode_witness(&witness)?,
+    })
+}
+
+// Certified variant of `get_deposits`; the witness proves the presence (or absence)
+// of `user` under staking/positions
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_certified_deposits(user: Principal) -> Option<CertifiedDeposits> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let positions = POSITIONS.with(|p| {
+        p.borrow().nested_witness(user.as_slice(), |tree| tree.as_hash_tree())
+    });
+    let total_hash = total_staked_tree().digest();
+    let witness = fork(
+        pruned(tip_hash()),
+        labeled("staking", fork(labeled("positions", positions), pruned(total_hash))),
+    );
+    let deposits = STATE.with(|s| {
+        s.borrow().get_user_deposits(&user).map(|ud| ud.deposits.clone()).unwrap_or_default()
+    });
+    Some(CertifiedDeposits {
+        deposits,
+        certificate,
+        witness: encode_witness(&witness)?,
+    })
+}
@@ -0,0 +1,185 @@
+// Append-only history of staking operations. Events live in stable memory, so they
+// survive upgrades and outlive the deposits they describe. Once an event has been
+// copied to an ICRC-3 archive it is dropped here (see `trim`); ids keep counting from
+// where they were. The per-principal index is kept on the heap and rebuilt from the
+// log after an upgrade.
+use candid::{candid_method, Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, StableCell, StableLog, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::icrc3;
+use crate::memory::{self, Memory};
+use crate::types::*;
+
+const MAX_PAGE_SIZE: u64 = 1000;
+
+thread_local! {
+    // Events not yet archived, by id
+    static EVENTS: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::EVENTS))
+    );
+
+    // Number of events ever recorded, archived ones included
+    static EVENT_COUNT: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(memory::get(memory::EVENT_COUNT), 0)
+            .expect("Failed to initialize the event count")
+    );
+
+    // Where earlier versions kept events; emptied by `migrate`
+    static LEGACY_EVENTS: RefCell<StableLog<Event, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            memory::get(memory::EVENT_INDEX),
+            memory::get(memory::EVENT_DATA),
+        ).expect("Failed to initialize the legacy event log")
+    );
+
+    // Kept apart from STATE so events can be recorded while STATE is borrowed
+    static USER_EVENTS: RefCell<HashMap<Principal, Vec<u64>>> = RefCell::new(HashMap::new());
+}
+
+impl Storable for Event {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode event"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Event).expect("Failed to decode event")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Principals whose history includes the event
+fn related_principals(event: &Event) -> Vec<Principal> {
+    match &event.kind {
+        EventKind::PositionTransferred { to, .. } => vec![event.principal, *to],
+        _ => vec![event.principal],
+    }
+}
+
+fn index_event(event: &Event) {
+    USER_EVENTS.with(|index| {
+        let mut index = index.borrow_mut();
+        for principal in related_principals(event) {
+            index.entry(principal).or_default().push(event.id);
+        }
+    });
+}
+
+// Append an event. A failed write is logged rather than trapping, since events are
+// often recorded after a ledger transfer has already gone through.
+pub(crate) fn record(principal: Principal, kind: EventKind) {
+    let id = len();
+    let event = Event { id, timestamp: time(), principal, kind };
+    match EVENT_COUNT.with(|count| count.borrow_mut().set(id + 1)) {
+        Ok(_) => {
+            EVENTS.with(|log| log.borrow_mut().insert(id, event.clone()));
+            index_event(&event);
+            icrc3::append_block(&event);
+        }
+        Err(e) => ic_cdk::println!("Failed to record event {:?}: {:?}", event, e),
+    }
+}
+
+// None once the event has been archived and trimmed
+pub(crate) fn get(id: u64) -> Option<Event> {
+    EVENTS.with(|log| log.borrow().get(&id))
+}
+
+pub(crate) fn len() -> u64 {
+    EVENT_COUNT.with(|count| *count.borrow().get())
+}
+
+// Drop events with ids below `end`. Called once they are safely in an archive.
+pub(crate) fn trim(end: u64) {
+    EVENTS.with(|log| {
+        let mut log = log.borrow_mut();
+        let ids: Vec<u64> = log.range(..end).map(|(id, _)| id).collect();
+        for id in ids {
+            log.remove(&id);
+        }
+    });
+    USER_EVENTS.with(|index| {
+        let mut index = index.borrow_mut();
+        for ids in index.values_mut() {
+            ids.retain(|id| *id >= end);
+        }
+        index.retain(|_, ids| !ids.is_empty());
+    });
+}
+
+// Move events from the log used by earlier versions. Runs after an upgrade, before the
+// index is rebuilt; the legacy log is reset afterwards, so this happens only once.
+pub(crate) fn migrate() {
+    let legacy_len = LEGACY_EVENTS.with(|log| log.borrow().len());
+    if legacy_len == 0 {
+        return;
+    }
+    LEGACY_EVENTS.with(|legacy| {
+        EVENTS.with(|log| {
+            let mut log = log.borrow_mut();
+            for event in legacy.borrow().iter() {
+                log.insert(event.id, event);
+            }
+        });
+    });
+    if let Err(e) = EVENT_COUNT.with(|count| count.borrow_mut().set(legacy_len.max(len()))) {
+        ic_cdk::trap(&format!("Failed to migrate the event count: {:?}", e));
+    }
+    LEGACY_EVENTS.with(|log| {
+        *log.borrow_mut() = StableLog::new(memory::get(memory::EVENT_INDEX), memory::get(memory::EVENT_DATA));
+    });
+    ic_cdk::println!("Migrated {} events", legacy_len);
+}
+
+// Called after an upgrade, when the heap index is empty again
+pub(crate) fn rebuild_index() {
+    EVENTS.with(|log| {
+        for (_, event) in log.borrow().iter() {
+            index_event(&event);
+        }
+    });
+}
+
+// Oldest first, starting at event id `offset`; `limit` is capped at 1000. Archived
+// events are no longer returned, see `icrc3_get_blocks`.
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_events(offset: u64, limit: u64) -> Vec<Event> {
+    EVENTS.with(|log| {
+        log.borrow().range(offset..)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_user_events(user: Principal, offset: u64, limit: u64) -> Vec<Event> {
+    let ids: Vec<u64> = USER_EVENTS.with(|index| {
+        index.borrow().get(&user)
+            .map(|ids| {
+                ids.iter()
+                    .skip(offset as usize)
+                    .take(limit.min(MAX_PAGE_SIZE) as usize)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+    EVENTS.with(|log| {
+        let log = log.borrow();
+        ids.into_iter().filter_map(|id| log.get(&id)).collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_event_count() -> u64 {
+    len()
+}
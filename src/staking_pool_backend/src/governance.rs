@@ -0,0 +1,355 @@
+// Stake-weighted governance over pool parameters. Voting power comes from locked
+// deposits and decays with the remaining lock time. Votes are recorded per deposit,
+// so moving or splitting a position cannot be used to vote twice with the same stake.
+// A proposal is finalized when its voting period ends. If it passed, its change is
+// queued in the timelock like an admin change, so stakers can exit before it applies.
+use candid::{candid_method, Principal};
+use ic_cdk::api::time;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use crate::types::*;
+use crate::{check_not_paused, events, reward_pool, timelock, STATE};
+
+const VOTING_PERIOD: u64 = 3 * 24 * 60 * 60; // in seconds
+const QUORUM_BPS: u128 = 1_000; // 10% of total voting power must take part
+const MIN_PROPOSER_POWER: u64 = 100_000_000; // 1 ICP locked for the maximum period
+const MAX_MULTIPLIER_BPS: u32 = 50_000;
+const MIN_REWARD_INTERVAL: u64 = 60 * 60; // in seconds
+const MAX_INSURANCE_BPS: u32 = 5_000;
+pub(crate) const MAX_PROTOCOL_FEE_BPS: u32 = 2_000;
+const MAX_VESTING_PERIOD: u64 = 365 * 24 * 60 * 60; // in seconds
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_VOTING_POWER_BATCH: usize = 500;
+
+#[derive(Default)]
+pub(crate) struct Governance {
+    proposals: BTreeMap<u64, Proposal>,
+    // Deposit ids that have already voted, per proposal
+    voted_deposits: HashMap<u64, BTreeSet<u64>>,
+    // Deposits created after a proposal was submitted cannot vote on it
+    eligible_below: HashMap<u64, u64>,
+    // Proposals that passed, by the id of the timelock action applying them
+    queued: HashMap<u64, u64>,
+    next_proposal_id: u64,
+}
+
+impl Governance {
+    // A merged position carries over the votes of the positions merged into it
+    pub(crate) fn inherit_votes(&mut self, from_deposit: u64, to_deposit: u64) {
+        for voted in self.voted_deposits.values_mut() {
+            if voted.contains(&from_deposit) {
+                voted.insert(to_deposit);
+            }
+        }
+    }
+}
+
+// Vote-escrow power of a single deposit: amount scaled by the lock time remaining at
+// `at_time`, relative to the longest lock tier. Unlocked deposits, and deposits made
+// after `at_time`, have no power.
+pub(crate) fn voting_power(deposit: &Deposit, at_time: u64) -> u64 {
+    if at_time < deposit.deposit_time {
+        return 0;
+    }
+    let remaining = deposit.unlock_time().saturating_sub(at_time) as u128;
+    let max_lock = LockPeriod::Days360.to_seconds() as u128 * 1_000_000_000;
+    (deposit.amount as u128 * remaining.min(max_lock) / max_lock) as u64
+}
+
+pub(crate) fn validate_change(change: &ParameterChange) -> StakingResult<()> {
+    match change {
+        ParameterChange::SetTierMultipliers(m) => {
+            let valid = [m.days90, m.days180, m.days360].iter()
+                .all(|bps| (1..=MAX_MULTIPLIER_BPS).contains(bps));
+            if valid {
+                Ok(())
+            } else {
+                Err(StakingError::InvalidArgument(format!("Multipliers must be between 1 and {} bps", MAX_MULTIPLIER_BPS)))
+            }
+        }
+        ParameterChange::SetRewardInterval(interval) => {
+            if *interval == 0 || *interval >= MIN_REWARD_INTERVAL {
+                Ok(())
+            } else {
+                Err(StakingError::InvalidArgument(format!("Reward interval must be 0 or at least {} seconds", MIN_REWARD_INTERVAL)))
+            }
+        }
+        ParameterChange::SetInsuranceShare(bps) => {
+            if *bps <= MAX_INSURANCE_BPS {
+                Ok(())
+            } else {
+                Err(StakingError::InvalidArgument(format!("Insurance share must be at most {} bps", MAX_INSURANCE_BPS)))
+            }
+        }
+        ParameterChange::SetProtocolFee(bps) => {
+            if *bps <= MAX_PROTOCOL_FEE_BPS {
+                Ok(())
+            } else {
+                Err(StakingError::InvalidArgument(format!("Protocol fee must be at most {} bps", MAX_PROTOCOL_FEE_BPS)))
+            }
+        }
+        ParameterChange::SetRewardVesting(period) => {
+            if *period <= MAX_VESTING_PERIOD {
+                Ok(())
+            } else {
+                Err(StakingError::InvalidArgument(format!("Vesting period must be at most {} seconds", MAX_VESTING_PERIOD)))
+            }
+        }
+    }
+}
+
+pub(crate) fn apply_change(change: ParameterChange) -> StakingResult<()> {
+    validate_change(&change)?;
+    events::record(ic_cdk::caller(), EventKind::ParameterChanged { change: change.clone() });
+    match change {
+        ParameterChange::SetTierMultipliers(multipliers) => {
+            STATE.with(|s| s.borrow_mut().parameters.tier_multipliers = multipliers);
+        }
+        ParameterChange::SetRewardInterval(interval) => {
+            STATE.with(|s| s.borrow_mut().parameters.reward_interval = interval);
+            schedule_rewards(interval);
+        }
+        ParameterChange::SetInsuranceShare(bps) => {
+            STATE.with(|s| s.borrow_mut().parameters.insurance_bps = bps);
+        }
+        ParameterChange::SetProtocolFee(bps) => {
+            STATE.with(|s| s.borrow_mut().parameters.protocol_fee_bps = bps);
+        }
+        // Grants made so far keep their schedule
+        ParameterChange::SetRewardVesting(period) => {
+            STATE.with(|s| s.borrow_mut().parameters.reward_vesting_period = period);
+        }
+    }
+    Ok(())
+}
+
+// Replace the reward timer so reward_pool runs every `interval` seconds
+fn schedule_rewards(interval: u64) {
+    let previous = STATE.with(|s| s.borrow_mut().reward_timer.take());
+    if let Some(timer) = previous {
+        ic_cdk_timers::clear_timer(timer);
+    }
+    if interval == 0 {
+        return;
+    }
+
+    let timer = ic_cdk_timers::set_timer_interval(Duration::from_secs(interval), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = reward_pool().await {
+                ic_cdk::println!("Scheduled reward distribution failed: {:?}", e);
+            }
+        });
+    });
+    STATE.with(|s| s.borrow_mut().reward_timer = Some(timer));
+}
+
+fn total_voting_power(at_time: u64) -> u64 {
+    STATE.with(|s| {
+        s.borrow().users.values()
+            .flat_map(|ud| ud.deposits.iter())
+            .map(|d| voting_power(d, at_time))
+            .sum()
+    })
+}
+
+fn user_voting_power(user: &Principal, at_time: u64) -> u64 {
+    STATE.with(|s| {
+        s.borrow().get_user_deposits(user)
+            .map(|ud| ud.deposits.iter().map(|d| voting_power(d, at_time)).sum())
+            .unwrap_or(0)
+    })
+}
+
+// Settle a proposal whose voting period has ended, queueing its change if it passed
+fn finalize(proposal_id: u64) {
+    let outcome = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let proposal = state.governance.proposals.get_mut(&proposal_id)?;
+        if !matches!(proposal.status, ProposalStatus::Open) || time() < proposal.voting_ends_at {
+            return None;
+        }
+
+        let turnout = proposal.yes_votes as u128 + proposal.no_votes as u128;
+        let quorum = proposal.total_voting_power as u128 * QUORUM_BPS / 10_000;
+        let passed = turnout >= quorum && proposal.yes_votes > proposal.no_votes;
+        if !passed {
+            proposal.status = ProposalStatus::Rejected;
+        }
+        Some((proposal.proposer, passed.then(|| proposal.change.clone())))
+    });
+    let Some((proposer, change)) = outcome else {
+        return;
+    };
+
+    let status = match change {
+        Some(change) => {
+            let action_id = timelock::enqueue(AdminAction::ChangeParameter(change), ic_cdk::id());
+            STATE.with(|s| {
+                let governance = &mut s.borrow_mut().governance;
+                governance.queued.insert(action_id, proposal_id);
+                if let Some(proposal) = governance.proposals.get_mut(&proposal_id) {
+                    proposal.status = ProposalStatus::Queued { action_id };
+                }
+            });
+            ProposalStatus::Queued { action_id }
+        }
+        None => ProposalStatus::Rejected,
+    };
+    ic_cdk::println!("Governance proposal {} finalized: {:?}", proposal_id, status);
+    events::record(proposer, EventKind::ProposalFinalized { proposal_id, status });
+}
+
+// Record how the timelock action applying a passed proposal ended
+pub(crate) fn settle_queued(action_id: u64, status: ProposalStatus) {
+    STATE.with(|s| {
+        let governance = &mut s.borrow_mut().governance;
+        let Some(proposal_id) = governance.queued.remove(&action_id) else {
+            return;
+        };
+        if let Some(proposal) = governance.proposals.get_mut(&proposal_id) {
+            proposal.status = status;
+        }
+    });
+}
+
+#[ic_cdk::update]
+#[candid_method(update)]
+fn submit_proposal(args: SubmitProposalArgs) -> StakingResult<u64> {
+    check_not_paused(PauseScope::All)?;
+    let caller = ic_cdk::caller();
+    let now = time();
+
+    if args.title.trim().is_empty() || args.title.len() > MAX_TITLE_LENGTH
+        || args.description.len() > MAX_DESCRIPTION_LENGTH
+    {
+        return Err(StakingError::InvalidArgument("Title or description has an invalid length".to_string()));
+    }
+    validate_change(&args.change)?;
+
+    if user_voting_power(&caller, now) < MIN_PROPOSER_POWER {
+        return Err(StakingError::NoVotingPower);
+    }
+
+    let total_power = total_voting_power(now);
+    let id = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let next_deposit_id = state.next_deposit_id;
+        let governance = &mut state.governance;
+        let id = governance.next_proposal_id;
+        governance.next_proposal_id += 1;
+        governance.proposals.insert(id, Proposal {
+            id,
+            proposer: caller,
+            title: args.title,
+            description: args.description,
+            change: args.change.clone(),
+            created_at: now,
+            voting_ends_at: now + VOTING_PERIOD * 1_000_000_000,
+            yes_votes: 0,
+            no_votes: 0,
+            total_voting_power: total_power,
+            status: ProposalStatus::Open,
+        });
+        governance.eligible_below.insert(id, next_deposit_id);
+        id
+    });
+
+    events::record(caller, EventKind::ProposalSubmitted { proposal_id: id, change: args.change });
+    ic_cdk_timers::set_timer(Duration::from_secs(VOTING_PERIOD), move || finalize(id));
+    Ok(id)
+}
+
+// Cast all of the caller's eligible, not yet used voting power. Returns the power cast.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn vote(proposal_id: u64, approve: bool) -> StakingResult<u64> {
+    check_not_paused(PauseScope::All)?;
+    let caller = ic_cdk::caller();
+    let now = time();
+
+    finalize(proposal_id);
+
+    STATE.with(|s| {
+        let state = &mut *s.borrow_mut();
+        let governance = &mut state.governance;
+        let proposal = governance.proposals.get_mut(&proposal_id)
+            .ok_or(StakingError::ProposalNotFound)?;
+        if !matches!(proposal.status, ProposalStatus::Open) {
+            return Err(StakingError::VotingClosed);
+        }
+
+        let eligible_below = governance.eligible_below.get(&proposal_id).copied().unwrap_or(0);
+        let voted = governance.voted_deposits.entry(proposal_id).or_default();
+        let mut power = 0u64;
+        for deposit in state.users.get(&caller).map(|ud| ud.deposits.as_slice()).unwrap_or_default() {
+            if deposit.id < eligible_below && !voted.contains(&deposit.id) {
+                power = power.saturating_add(voting_power(deposit, now));
+                voted.insert(deposit.id);
+            }
+        }
+
+        if power == 0 {
+            return Err(StakingError::NoVotingPower);
+        }
+        if approve {
+            proposal.yes_votes = proposal.yes_votes.saturating_add(power);
+        } else {
+            proposal.no_votes = proposal.no_votes.saturating_add(power);
+        }
+        events::record(caller, EventKind::VoteCast { proposal_id, approve, power });
+        Ok(power)
+    })
+}
+
+// Finalize a proposal whose voting period has ended. Normally done by a timer; this
+// covers timers lost on upgrade.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn finalize_proposal(proposal_id: u64) -> StakingResult<ProposalStatus> {
+    finalize(proposal_id);
+    STATE.with(|s| {
+        s.borrow().governance.proposals.get(&proposal_id)
+            .map(|p| p.status.clone())
+            .ok_or(StakingError::ProposalNotFound)
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_proposal(proposal_id: u64) -> Option<Proposal> {
+    STATE.with(|s| s.borrow().governance.proposals.get(&proposal_id).cloned())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_proposals() -> Vec<Proposal> {
+    STATE.with(|s| s.borrow().governance.proposals.values().cloned().collect())
+}
+
+// Vote-escrow voting power of `user` at `at_time` (nanoseconds, defaults to now), for
+// other canisters gating features on locked stake. Computed from current positions, so
+// times in the past don't reflect positions that have since been withdrawn or moved.
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_voting_power(user: Principal, at_time: Option<u64>) -> u64 {
+    user_voting_power(&user, at_time.unwrap_or_else(time))
+}
+
+// Batch variant of `get_voting_power`, limited to 500 principals per call
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_voting_powers(users: Vec<Principal>, at_time: Option<u64>) -> Vec<(Principal, u64)> {
+    let at_time = at_time.unwrap_or_else(time);
+    users.into_iter()
+        .take(MAX_VOTING_POWER_BATCH)
+        .map(|user| (user, user_voting_power(&user, at_time)))
+        .collect()
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_pool_parameters() -> PoolParameters {
+    STATE.with(|s| s.borrow().parameters.clone())
+}
@@ -0,0 +1,560 @@
+// ICRC-3 view of the event log. Every event is rendered as a block whose hash chains to
+// the previous block, and the tip is certified (see `certification`) so clients can
+// verify the log without trusting a single replica. Once the local log grows past
+// ARCHIVE_THRESHOLD blocks, the oldest ones are copied to archive canisters created
+// from the wasm uploaded with `set_archive_wasm`, dropped locally, and
+// `icrc3_get_blocks` points clients there for those ranges.
+use candid::{candid_method, CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, CanisterInstallMode, CanisterSettings, CreateCanisterArgument,
+    InstallCodeArgument,
+};
+use ic_certification::{fork, labeled, leaf, HashTree};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, StableCell, StableVec, Storable};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::memory::{self, Memory};
+use crate::types::*;
+use crate::{certification, events, require_controller};
+
+const ARCHIVE_THRESHOLD: u64 = 2_000; // unarchived blocks that trigger archiving
+const BLOCKS_KEPT_LOCALLY: u64 = 1_000; // newest blocks left in place after archiving
+const BLOCKS_PER_ARCHIVE: u64 = 1_000_000;
+const APPEND_BATCH_SIZE: u64 = 200;
+const ARCHIVE_CREATION_CYCLES: u128 = 2_000_000_000_000;
+const MAX_BLOCKS_PER_RESPONSE: u64 = 100;
+const BLOCK_TYPES_URL: &str = "https://github.com/jeetnik/staking_pool_ICP";
+
+type Hash = [u8; 32];
+
+// Blocks [start, end) live in `canister_id`; the start index is the map key
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct ArchiveRange {
+    canister_id: Principal,
+    end: u64,
+}
+
+impl Storable for ArchiveRange {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode archive range"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), ArchiveRange).expect("Failed to decode archive range")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Hash of block i is stored at index i
+    static BLOCK_HASHES: RefCell<StableVec<Hash, Memory>> = RefCell::new(
+        StableVec::init(memory::get(memory::BLOCK_HASHES)).expect("Failed to initialize block hashes")
+    );
+
+    static ARCHIVES: RefCell<StableBTreeMap<u64, ArchiveRange, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::ARCHIVES))
+    );
+
+    static ARCHIVE_WASM: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(memory::get(memory::ARCHIVE_WASM), Vec::new())
+            .expect("Failed to initialize the archive wasm cell")
+    );
+
+    static ARCHIVING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Clears the archiving flag when an archiving run ends, however it ends
+struct ArchivingGuard;
+
+impl Drop for ArchivingGuard {
+    fn drop(&mut self) {
+        ARCHIVING.with(|a| a.set(false));
+    }
+}
+
+// Representation-independent hash of a value, as specified by ICRC-3
+fn hash_value(value: &Value) -> Hash {
+    let mut hasher = Sha256::new();
+    match value {
+        Value::Blob(bytes) => hasher.update(bytes),
+        Value::Text(text) => hasher.update(text.as_bytes()),
+        Value::Nat(nat) => {
+            let mut buf = Vec::new();
+            nat.encode(&mut buf).expect("Failed to encode nat");
+            hasher.update(buf);
+        }
+        Value::Int(int) => {
+            let mut buf = Vec::new();
+            int.encode(&mut buf).expect("Failed to encode int");
+            hasher.update(buf);
+        }
+        Value::Array(values) => {
+            for value in values {
+                hasher.update(hash_value(value));
+            }
+        }
+        Value::Map(entries) => {
+            let mut pairs: Vec<Vec<u8>> = entries.iter()
+                .map(|(key, value)| [Sha256::digest(key.as_bytes()).as_slice(), &hash_value(value)].concat())
+                .collect();
+            pairs.sort();
+            for pair in pairs {
+                hasher.update(pair);
+            }
+        }
+    }
+    hasher.finalize().into()
+}
+
+fn nat(n: u64) -> Value {
+    Value::Nat(Nat::from(n))
+}
+
+fn principal(p: &Principal) -> Value {
+    Value::Blob(p.as_slice().to_vec())
+}
+
+// Block type and transaction fields for an event
+fn describe(event: &Event) -> (&'static str, Vec<(String, Value)>) {
+    let mut tx = vec![("principal".to_string(), principal(&event.principal))];
+    let mut field = |name: &str, value: Value| tx.push((name.to_string(), value));
+
+    let btype = match &event.kind {
+        EventKind::DepositIntentionCreated { subaccount, expected_amount, lock_period } => {
+            field("subaccount", Value::Blob(subaccount.0.to_vec()));
+            field("amount", nat(*expected_amount));
+            field("lock_period", nat(*lock_period));
+            "stake_intention"
+        }
+        EventKind::DepositIntentionExpired { subaccount } => {
+            field("subaccount", Value::Blob(subaccount.0.to_vec()));
+            "stake_intention_expired"
+        }
+        EventKind::DepositConfirmed { deposit_id, amount, lock_period } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            field("lock_period", nat(*lock_period));
+            "stake_deposit"
+        }
+        EventKind::Withdrawal { deposit_id, amount, block_index } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            field("ledger_block", nat(*block_index));
+            "stake_withdraw"
+        }
+        EventKind::EmergencyWithdrawal { deposit_id, amount, block_index } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            field("ledger_block", nat(*block_index));
+            "stake_emergency_withdraw"
+        }
+        EventKind::PositionSplit { deposit_id, new_deposit_id, amount, block_index } => {
+            field("deposit_id", nat(*deposit_id));
+            field("new_deposit_id", nat(*new_deposit_id));
+            field("amount", nat(*amount));
+            field("ledger_block", nat(*block_index));
+            "stake_split"
+        }
+        EventKind::PositionMerged { deposit_id, into_deposit_id, amount, block_index } => {
+            field("deposit_id", nat(*deposit_id));
+            field("into_deposit_id", nat(*into_deposit_id));
+            field("amount", nat(*amount));
+            field("ledger_block", nat(*block_index));
+            "stake_merge"
+        }
+        EventKind::PositionTransferred { deposit_id, to } => {
+            field("deposit_id", nat(*deposit_id));
+            field("to", principal(to));
+            "stake_transfer"
+        }
+        EventKind::RewardPaid { deposit_id, amount, block_index } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            field("ledger_block", nat(*block_index));
+            "stake_reward"
+        }
+        EventKind::RewardAccrued { deposit_id, amount } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            "stake_reward_accrual"
+        }
+        EventKind::RewardsClaimed { deposit_ids, amount, block_index } => {
+            field("deposit_ids", Value::Array(deposit_ids.iter().map(|id| nat(*id)).collect()));
+            field("amount", nat(*amount));
+            field("ledger_block", nat(*block_index));
+            "stake_reward_claim"
+        }
+        EventKind::RewardsForfeited { deposit_id, amount } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            "stake_reward_forfeit"
+        }
+        EventKind::Slashed { deposit_id, amount, receiver, block_index } => {
+            field("deposit_id", nat(*deposit_id));
+            field("amount", nat(*amount));
+            field("receiver", principal(receiver));
+            field("ledger_block", nat(*block_index));
+            "stake_slash"
+        }
+        EventKind::HeldSlashDelivered { slash_id, amount, receiver, block_index } => {
+            field("slash_id", nat(*slash_id));
+            field("amount", nat(*amount));
+            field("receiver", principal(receiver));
+            field("ledger_block", nat(*block_index));
+            "stake_slash_delivery"
+        }
+        EventKind::AdminActionQueued { action_id, action } => {
+            field("action_id", nat(*action_id));
+            field("action", Value::Text(format!("{:?}", action)));
+            "stake_admin_queue"
+        }
+        EventKind::AdminActionExecuted { action_id, action } => {
+            field("action_id", nat(*action_id));
+            field("action", Value::Text(format!("{:?}", action)));
+            "stake_admin"
+        }
+        EventKind::AdminActionCancelled { action_id } => {
+            field("action_id", nat(*action_id));
+            "stake_admin_cancel"
+        }
+        EventKind::ParameterChanged { change } => {
+            field("change", Value::Text(format!("{:?}", change)));
+            "stake_parameter"
+        }
+        EventKind::ProposalSubmitted { proposal_id, change } => {
+            field("proposal_id", nat(*proposal_id));
+            field("change", Value::Text(format!("{:?}", change)));
+            "stake_proposal"
+        }
+        EventKind::VoteCast { proposal_id, approve, power } => {
+            field("proposal_id", nat(*proposal_id));
+            field("approve", Value::Text(approve.to_string()));
+            field("power", nat(*power));
+            "stake_vote"
+        }
+        EventKind::ProposalFinalized { proposal_id, status } => {
+            field("proposal_id", nat(*proposal_id));
+            field("status", Value::Text(format!("{:?}", status)));
+            "stake_proposal_final"
+        }
+        EventKind::SlashProposed { proposal_id, amount, receiver } => {
+            field("proposal_id", nat(*proposal_id));
+            field("amount", nat(*amount));
+            field("receiver", principal(receiver));
+            "stake_slash_proposal"
+        }
+        EventKind::SlashApproved { proposal_id } => {
+            field("proposal_id", nat(*proposal_id));
+            "stake_slash_approval"
+        }
+        EventKind::AutoCompoundSet { deposit_id, enabled } => {
+            field("deposit_id", nat(*deposit_id));
+            field("enabled", Value::Text(enabled.to_string()));
+            "stake_auto_compound"
+        }
+        EventKind::PositionOffered { deposit_id, to } => {
+            field("deposit_id", nat(*deposit_id));
+            field("to", principal(to));
+            "stake_offer"
+        }
+        EventKind::PositionOfferCancelled { deposit_id } => {
+            field("deposit_id", nat(*deposit_id));
+            "stake_offer_cancel"
+        }
+        EventKind::SnapshotTaken { snapshot_id } => {
+            field("snapshot_id", nat(*snapshot_id));
+            "stake_snapshot"
+        }
+        EventKind::ArchiveWasmSet { size } => {
+            field("size", nat(*size));
+            "stake_archive_wasm"
+        }
+        EventKind::Paused { scope } => {
+            field("scope", Value::Text(format!("{:?}", scope)));
+            "stake_pause"
+        }
+        EventKind::Unpaused { scope } => {
+            field("scope", Value::Text(format!("{:?}", scope)));
+            "stake_unpause"
+        }
+        EventKind::EmergencyModeSet { enabled } => {
+            field("enabled", Value::Text(enabled.to_string()));
+            "stake_emergency_mode"
+        }
+        EventKind::DepositCorrected { deposit_id, recorded, ledger } => {
+            field("deposit_id", nat(*deposit_id));
+            field("recorded", nat(*recorded));
+            field("ledger", nat(*ledger));
+            "stake_correction"
+        }
+    };
+    (btype, tx)
+}
+
+fn to_block(event: &Event, parent_hash: Option<Hash>) -> Value {
+    let (btype, tx) = describe(event);
+    let mut fields = vec![
+        ("btype".to_string(), Value::Text(btype.to_string())),
+        ("ts".to_string(), nat(event.timestamp)),
+        ("tx".to_string(), Value::Map(tx)),
+    ];
+    if let Some(parent_hash) = parent_hash {
+        fields.push(("phash".to_string(), Value::Blob(parent_hash.to_vec())));
+    }
+    Value::Map(fields)
+}
+
+fn block_count() -> u64 {
+    BLOCK_HASHES.with(|h| h.borrow().len())
+}
+
+fn block_hash(index: u64) -> Option<Hash> {
+    BLOCK_HASHES.with(|h| h.borrow().get(index))
+}
+
+fn block(index: u64) -> Option<Value> {
+    if index >= block_count() {
+        return None;
+    }
+    let parent_hash = match index.checked_sub(1) {
+        Some(parent) => Some(block_hash(parent)?),
+        None => None,
+    };
+    Some(to_block(&events::get(index)?, parent_hash))
+}
+
+// First block index that is still only held locally
+fn archived_end() -> u64 {
+    ARCHIVES.with(|a| a.borrow().last_key_value().map_or(0, |(_, range)| range.end))
+}
+
+pub(crate) fn tip_tree() -> Option<HashTree> {
+    let last_index = block_count().checked_sub(1)?;
+    let mut index_leb = Vec::new();
+    Nat::from(last_index).encode(&mut index_leb).ok()?;
+    Some(fork(
+        labeled("last_block_hash", leaf(block_hash(last_index)?.to_vec())),
+        labeled("last_block_index", leaf(index_leb)),
+    ))
+}
+
+// Chain a newly recorded event into the block log
+pub(crate) fn append_block(event: &Event) {
+    if event.id != block_count() {
+        ic_cdk::println!("Block log out of sync at event {}, run backfill_blocks", event.id);
+        return;
+    }
+    let parent_hash = event.id.checked_sub(1).and_then(block_hash);
+    let hash = hash_value(&to_block(event, parent_hash));
+    if let Err(e) = BLOCK_HASHES.with(|h| h.borrow().push(&hash)) {
+        ic_cdk::println!("Failed to store hash of block {}: {:?}", event.id, e);
+        return;
+    }
+    certification::certify();
+    schedule_archiving();
+}
+
+// Hash any events recorded before the block log existed (or while it was out of sync)
+pub(crate) fn backfill_blocks() {
+    for id in block_count()..events::len() {
+        if let Some(event) = events::get(id) {
+            let parent_hash = id.checked_sub(1).and_then(block_hash);
+            let hash = hash_value(&to_block(&event, parent_hash));
+            if BLOCK_HASHES.with(|h| h.borrow().push(&hash)).is_err() {
+                break;
+            }
+        }
+    }
+    certification::certify();
+}
+
+fn schedule_archiving() {
+    let due = block_count().saturating_sub(archived_end()) > ARCHIVE_THRESHOLD;
+    let has_wasm = ARCHIVE_WASM.with(|w| !w.borrow().get().is_empty());
+    if !due || !has_wasm || ARCHIVING.with(|a| a.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            let _guard = ArchivingGuard;
+            if let Err(e) = archive_blocks().await {
+                ic_cdk::println!("Archiving failed: {}", e);
+            }
+        });
+    });
+}
+
+async fn create_archive(start: u64) -> Result<Principal, String> {
+    let wasm_module = ARCHIVE_WASM.with(|w| w.borrow().get().clone());
+    let settings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::id()]),
+        ..Default::default()
+    };
+    let (record,) = create_canister(CreateCanisterArgument { settings: Some(settings) }, ARCHIVE_CREATION_CYCLES)
+        .await
+        .map_err(|(code, msg)| format!("Failed to create archive: {:?} - {}", code, msg))?;
+
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: record.canister_id,
+        wasm_module,
+        arg: Encode!().expect("Failed to encode archive init args"),
+    })
+    .await
+    .map_err(|(code, msg)| format!("Failed to install archive: {:?} - {}", code, msg))?;
+
+    ARCHIVES.with(|a| a.borrow_mut().insert(start, ArchiveRange { canister_id: record.canister_id, end: start }));
+    ic_cdk::println!("Created archive {} starting at block {}", record.canister_id, start);
+    Ok(record.canister_id)
+}
+
+// Copy all but the newest BLOCKS_KEPT_LOCALLY blocks to archives, in batches. Progress
+// is recorded after every batch, so a failed run resumes where it stopped.
+async fn archive_blocks() -> Result<(), String> {
+    let target_end = block_count().saturating_sub(BLOCKS_KEPT_LOCALLY);
+
+    loop {
+        let start = archived_end();
+        if start >= target_end {
+            return Ok(());
+        }
+
+        let current = ARCHIVES.with(|a| {
+            a.borrow().last_key_value()
+                .filter(|(first, range)| range.end - first < BLOCKS_PER_ARCHIVE)
+                .map(|(first, range)| (first, range.canister_id))
+        });
+        let (first, archive) = match current {
+            Some(current) => current,
+            None => (start, create_archive(start).await?),
+        };
+
+        let end = target_end.min(start + APPEND_BATCH_SIZE).min(first + BLOCKS_PER_ARCHIVE);
+        let blocks: Vec<BlockWithId> = (start..end)
+            .map(|id| block(id).map(|block| BlockWithId { id: Nat::from(id), block }))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Blocks {}..{} are not available", start, end))?;
+
+        let (result,): (Result<(), String>,) = ic_cdk::call(archive, "append_blocks", (blocks,))
+            .await
+            .map_err(|(code, msg)| format!("Failed to append to archive {}: {:?} - {}", archive, code, msg))?;
+        result?;
+
+        ARCHIVES.with(|a| a.borrow_mut().insert(first, ArchiveRange { canister_id: archive, end }));
+        // The archive now serves these blocks; their hashes stay to chain new blocks
+        events::trim(end);
+    }
+}
+
+// Archives are created from this module; upload it before the log reaches the threshold
+#[ic_cdk::update]
+#[candid_method(update)]
+fn set_archive_wasm(wasm: Vec<u8>) -> StakingResult<()> {
+    require_controller()?;
+    if wasm.is_empty() {
+        return Err(StakingError::InvalidArgument("Archive wasm must not be empty".to_string()));
+    }
+    let size = wasm.len() as u64;
+    ARCHIVE_WASM.with(|w| w.borrow_mut().set(wasm))
+        .map_err(|e| StakingError::InvalidArgument(format!("Failed to store archive wasm: {:?}", e)))?;
+    events::record(ic_cdk::caller(), EventKind::ArchiveWasmSet { size });
+    schedule_archiving();
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let log_length = block_count();
+    let local_start = archived_end();
+    let mut blocks = Vec::new();
+    let mut archived: BTreeMap<Principal, Vec<GetBlocksArgs>> = BTreeMap::new();
+
+    for arg in args {
+        let start = u64::try_from(&arg.start.0).unwrap_or(u64::MAX);
+        let length = u64::try_from(&arg.length.0).unwrap_or(u64::MAX);
+        let end = start.saturating_add(length).min(log_length);
+
+        ARCHIVES.with(|a| {
+            for (first, range) in a.borrow().iter() {
+                let (from, to) = (start.max(first), end.min(range.end));
+                if from < to {
+                    archived.entry(range.canister_id).or_default().push(GetBlocksArgs {
+                        start: Nat::from(from),
+                        length: Nat::from(to - from),
+                    });
+                }
+            }
+        });
+
+        for id in start.max(local_start)..end {
+            if blocks.len() as u64 >= MAX_BLOCKS_PER_RESPONSE {
+                break;
+            }
+            if let Some(block) = block(id) {
+                blocks.push(BlockWithId { id: Nat::from(id), block });
+            }
+        }
+    }
+
+    GetBlocksResult {
+        log_length: Nat::from(log_length),
+        blocks,
+        archived_blocks: archived.into_iter()
+            .map(|(canister_id, args)| ArchivedBlocks {
+                args,
+                callback: GetBlocksCallback::new(canister_id, "icrc3_get_blocks".to_string()),
+            })
+            .collect(),
+    }
+}
+
+// Archives with a canister id greater than `from`, with the (inclusive) block range each holds
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc3_get_archives(args: GetArchivesArgs) -> Vec<ArchiveInfo> {
+    let mut archives: Vec<ArchiveInfo> = ARCHIVES.with(|a| {
+        a.borrow().iter()
+            .filter(|(first, range)| range.end > *first)
+            .filter(|(_, range)| args.from.is_none_or(|from| range.canister_id > from))
+            .map(|(first, range)| ArchiveInfo {
+                canister_id: range.canister_id,
+                start: Nat::from(first),
+                end: Nat::from(range.end - 1),
+            })
+            .collect()
+    });
+    archives.sort_by_key(|archive| archive.canister_id);
+    archives
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let hash_tree = certification::encode_witness(&certification::tip_witness()?)?;
+    Some(DataCertificate { certificate, hash_tree })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc3_supported_block_types() -> Vec<BlockType> {
+    [
+        "stake_intention", "stake_intention_expired", "stake_deposit", "stake_withdraw",
+        "stake_emergency_withdraw", "stake_split", "stake_merge", "stake_transfer",
+        "stake_reward", "stake_reward_accrual", "stake_reward_claim", "stake_reward_forfeit", "stake_slash", "stake_slash_delivery",
+        "stake_admin_queue", "stake_admin", "stake_admin_cancel", "stake_parameter", "stake_proposal",
+        "stake_vote", "stake_proposal_final", "stake_slash_proposal", "stake_slash_approval",
+        "stake_auto_compound", "stake_offer", "stake_offer_cancel", "stake_snapshot", "stake_archive_wasm",
+        "stake_pause", "stake_unpause", "stake_emergency_mode", "stake_correction",
+    ]
+    .iter()
+    .map(|btype| BlockType { block_type: btype.to_string(), url: BLOCK_TYPES_URL.to_string() })
+    .collect()
+}
@@ -0,0 +1,323 @@
+// ICRC-7 view of staking positions. Each deposit is exposed as a token whose id is the
+// deposit id, owned by the principal holding the position. Transferring the token moves
+// the position, so withdrawal rights follow token ownership. Transfers that set
+// `created_at_time` are deduplicated within the transaction window.
+use candid::{candid_method, Nat, Principal};
+use ic_cdk::api::time;
+use sha2::{Digest, Sha256};
+
+use crate::types::*;
+use crate::STATE;
+
+const SYMBOL: &str = "STKPOS";
+const NAME: &str = "Staking Pool Positions";
+const DESCRIPTION: &str = "Locked staking positions: amount, lock tier and unlock time";
+const MAX_QUERY_BATCH_SIZE: usize = 100;
+const MAX_UPDATE_BATCH_SIZE: usize = 20;
+const DEFAULT_TAKE_VALUE: usize = 100;
+const MAX_TAKE_VALUE: usize = 1000;
+const MAX_MEMO_SIZE: usize = 32;
+const TX_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours in nanoseconds
+const PERMITTED_DRIFT: u64 = 2 * 60 * 1_000_000_000; // 2 minutes in nanoseconds
+
+fn to_token_id(id: &Nat) -> Option<u64> {
+    u64::try_from(&id.0).ok()
+}
+
+// Positions are held per principal, so only the default subaccount can own tokens
+fn is_default_subaccount(subaccount: &Option<ic_ledger_types::Subaccount>) -> bool {
+    subaccount.is_none_or(|s| s.0 == [0u8; 32])
+}
+
+fn take_limit(take: Option<Nat>) -> usize {
+    take.and_then(|t| usize::try_from(&t.0).ok())
+        .unwrap_or(DEFAULT_TAKE_VALUE)
+        .min(MAX_TAKE_VALUE)
+}
+
+fn paginate(mut ids: Vec<u64>, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    ids.sort_unstable();
+    let start = prev.and_then(|p| to_token_id(&p))
+        .map_or(0, |p| ids.partition_point(|id| *id <= p));
+    ids[start..].iter().take(take_limit(take)).map(|id| Nat::from(*id)).collect()
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_collection_metadata() -> Vec<(String, Value)> {
+    vec![
+        ("icrc7:symbol".to_string(), Value::Text(SYMBOL.to_string())),
+        ("icrc7:name".to_string(), Value::Text(NAME.to_string())),
+        ("icrc7:description".to_string(), Value::Text(DESCRIPTION.to_string())),
+        ("icrc7:total_supply".to_string(), Value::Nat(icrc7_total_supply())),
+        ("icrc7:max_query_batch_size".to_string(), Value::Nat(Nat::from(MAX_QUERY_BATCH_SIZE))),
+        ("icrc7:max_update_batch_size".to_string(), Value::Nat(Nat::from(MAX_UPDATE_BATCH_SIZE))),
+        ("icrc7:default_take_value".to_string(), Value::Nat(Nat::from(DEFAULT_TAKE_VALUE))),
+        ("icrc7:max_take_value".to_string(), Value::Nat(Nat::from(MAX_TAKE_VALUE))),
+        ("icrc7:max_memo_size".to_string(), Value::Nat(Nat::from(MAX_MEMO_SIZE))),
+        ("icrc7:atomic_batch_transfers".to_string(), Value::Text("false".to_string())),
+        ("icrc7:tx_window".to_string(), Value::Nat(Nat::from(TX_WINDOW))),
+        ("icrc7:permitted_drift".to_string(), Value::Nat(Nat::from(PERMITTED_DRIFT))),
+    ]
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_symbol() -> String {
+    SYMBOL.to_string()
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_name() -> String {
+    NAME.to_string()
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_description() -> Option<String> {
+    Some(DESCRIPTION.to_string())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_total_supply() -> Nat {
+    STATE.with(|s| {
+        let count: usize = s.borrow().users.values().map(|ud| ud.deposits.len()).sum();
+        Nat::from(count)
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_supply_cap() -> Option<Nat> {
+    None
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_max_query_batch_size() -> Option<Nat> {
+    Some(Nat::from(MAX_QUERY_BATCH_SIZE))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_max_update_batch_size() -> Option<Nat> {
+    Some(Nat::from(MAX_UPDATE_BATCH_SIZE))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_default_take_value() -> Option<Nat> {
+    Some(Nat::from(DEFAULT_TAKE_VALUE))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_max_take_value() -> Option<Nat> {
+    Some(Nat::from(MAX_TAKE_VALUE))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_max_memo_size() -> Option<Nat> {
+    Some(Nat::from(MAX_MEMO_SIZE))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_atomic_batch_transfers() -> Option<bool> {
+    Some(false)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_tx_window() -> Option<Nat> {
+    Some(Nat::from(TX_WINDOW))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_permitted_drift() -> Option<Nat> {
+    Some(Nat::from(PERMITTED_DRIFT))
+}
+
+// Amount, lock tier, deposit time and unlock time of each position
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_token_metadata(token_ids: Vec<Nat>) -> Vec<Option<Vec<(String, Value)>>> {
+    STATE.with(|s| {
+        let state = s.borrow();
+        token_ids.iter().take(MAX_QUERY_BATCH_SIZE).map(|token_id| {
+            let (owner, index) = state.find_deposit(to_token_id(token_id)?)?;
+            let deposit = &state.users[&owner].deposits[index];
+            let tier = LockPeriod::from_seconds(deposit.lock_period)
+                .map_or("Custom", |tier| tier.name());
+            Some(vec![
+                ("staking:amount".to_string(), Value::Nat(Nat::from(deposit.amount))),
+                ("staking:tier".to_string(), Value::Text(tier.to_string())),
+                ("staking:lock_period".to_string(), Value::Nat(Nat::from(deposit.lock_period))),
+                ("staking:deposit_time".to_string(), Value::Nat(Nat::from(deposit.deposit_time))),
+                ("staking:unlock_time".to_string(), Value::Nat(Nat::from(deposit.unlock_time()))),
+            ])
+        }).collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    STATE.with(|s| {
+        let state = s.borrow();
+        token_ids.iter().take(MAX_QUERY_BATCH_SIZE).map(|token_id| {
+            let (owner, _) = state.find_deposit(to_token_id(token_id)?)?;
+            Some(Account { owner, subaccount: None })
+        }).collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_balance_of(accounts: Vec<Account>) -> Vec<Nat> {
+    STATE.with(|s| {
+        let state = s.borrow();
+        accounts.iter().take(MAX_QUERY_BATCH_SIZE).map(|account| {
+            if !is_default_subaccount(&account.subaccount) {
+                return Nat::from(0u64);
+            }
+            let count = state.get_user_deposits(&account.owner).map_or(0, |ud| ud.deposits.len());
+            Nat::from(count)
+        }).collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_tokens(prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    let ids = STATE.with(|s| {
+        s.borrow().users.values()
+            .flat_map(|ud| ud.deposits.iter().map(|d| d.id))
+            .collect()
+    });
+    paginate(ids, prev, take)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc7_tokens_of(account: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    if !is_default_subaccount(&account.subaccount) {
+        return Vec::new();
+    }
+    let ids = STATE.with(|s| {
+        s.borrow().get_user_deposits(&account.owner)
+            .map(|ud| ud.deposits.iter().map(|d| d.id).collect())
+            .unwrap_or_default()
+    });
+    paginate(ids, prev, take)
+}
+
+// Transfers are applied one by one; a failing entry does not affect the others
+#[ic_cdk::update]
+#[candid_method(update)]
+fn icrc7_transfer(args: Vec<TransferArg>) -> Vec<Option<TransferResult>> {
+    let caller = ic_cdk::caller();
+
+    if crate::check_not_paused(PauseScope::All).is_err() {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(4u64),
+            message: "Transfers are paused".to_string(),
+        }))];
+    }
+    if args.len() > MAX_UPDATE_BATCH_SIZE {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(1u64),
+            message: format!("Batch exceeds {} transfers", MAX_UPDATE_BATCH_SIZE),
+        }))];
+    }
+
+    args.into_iter().map(|arg| Some(transfer_one(caller, arg))).collect()
+}
+
+// Two transfers are the same if the same caller sent identical arguments
+fn transfer_hash(caller: &Principal, arg: &TransferArg) -> [u8; 32] {
+    let bytes = candid::encode_args((caller, arg)).expect("Failed to encode transfer");
+    Sha256::digest(bytes).into()
+}
+
+fn transfer_one(caller: Principal, arg: TransferArg) -> TransferResult {
+    let now = time();
+
+    if let Some(created_at_time) = arg.created_at_time {
+        if created_at_time > now.saturating_add(PERMITTED_DRIFT) {
+            return Err(TransferError::CreatedInFuture { ledger_time: now });
+        }
+        if created_at_time.saturating_add(TX_WINDOW + PERMITTED_DRIFT) < now {
+            return Err(TransferError::TooOld);
+        }
+    }
+    let dedup_key = arg.created_at_time.map(|created_at_time| (created_at_time, transfer_hash(&caller, &arg)));
+    if let Some(tx_index) = dedup_key.and_then(|key| STATE.with(|s| s.borrow().icrc7_recent.get(&key).copied())) {
+        return Err(TransferError::Duplicate { duplicate_of: Nat::from(tx_index) });
+    }
+    if arg.memo.as_ref().is_some_and(|memo| memo.len() > MAX_MEMO_SIZE) {
+        return Err(TransferError::GenericError {
+            error_code: Nat::from(2u64),
+            message: "Memo too long".to_string(),
+        });
+    }
+    if !is_default_subaccount(&arg.from_subaccount) {
+        return Err(TransferError::Unauthorized);
+    }
+    if !is_default_subaccount(&arg.to.subaccount)
+        || arg.to.owner == caller
+        || arg.to.owner == Principal::anonymous()
+    {
+        return Err(TransferError::InvalidRecipient);
+    }
+
+    let token_id = to_token_id(&arg.token_id).ok_or(TransferError::NonExistingTokenId)?;
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        match state.find_deposit(token_id) {
+            None => return Err(TransferError::NonExistingTokenId),
+            Some((owner, _)) if owner != caller => return Err(TransferError::Unauthorized),
+            Some(_) => {}
+        }
+
+        state.move_deposit(token_id, &caller, &arg.to.owner)
+            .map_err(|e| TransferError::GenericError {
+                error_code: Nat::from(3u64),
+                message: format!("{:?}", e),
+            })?;
+
+        let tx_index = state.icrc7_tx_count;
+        state.icrc7_tx_count += 1;
+        if let Some(key) = dedup_key {
+            // Anything older would be rejected as TooOld anyway
+            let cutoff = now.saturating_sub(TX_WINDOW + PERMITTED_DRIFT);
+            state.icrc7_recent = state.icrc7_recent.split_off(&(cutoff, [0; 32]));
+            state.icrc7_recent.insert(key, tx_index);
+        }
+        Ok(Nat::from(tx_index))
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn icrc10_supported_standards() -> Vec<SupportedStandard> {
+    vec![
+        SupportedStandard {
+            name: "ICRC-3".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-7".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10".to_string(),
+        },
+    ]
+}
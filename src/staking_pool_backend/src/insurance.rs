@@ -0,0 +1,61 @@
+// Insurance fund. A share of every reward epoch (`insurance_bps`) is routed to the
+// insurance subaccount, and anyone can top the fund up by sending ICP to its address.
+// `slash_pool` pays out of the fund first and only slashes deposits for the rest.
+use candid::candid_method;
+use ic_cdk::api::time;
+use ic_ledger_types::{AccountIdentifier, Subaccount};
+
+use crate::types::*;
+use crate::{reconcile, STATE};
+
+#[derive(Default)]
+pub(crate) struct Insurance {
+    pub(crate) contributed: u64, // routed from rewards
+    pub(crate) paid_out: u64,    // used to cover slashes
+    // Ledger balance and when it was last read
+    pub(crate) balance: Option<(u64, u64)>,
+}
+
+// Read the fund's balance from the ledger and remember it for `get_insurance_fund`
+pub(crate) async fn read_balance(subaccount: Subaccount) -> Option<u64> {
+    let balance = reconcile::ledger_balance(subaccount).await?;
+    STATE.with(|s| s.borrow_mut().insurance.balance = Some((balance, time())));
+    Some(balance)
+}
+
+// Adjust the remembered balance after a transfer into or out of the fund
+pub(crate) fn adjust_balance(received: u64, spent: u64) {
+    STATE.with(|s| {
+        if let Some((balance, _)) = &mut s.borrow_mut().insurance.balance {
+            *balance = balance.saturating_add(received).saturating_sub(spent);
+        }
+    });
+}
+
+// The balance is as of the last reward run, slash or `refresh_insurance_balance`, so
+// it may not include recent top-ups yet
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_insurance_fund() -> InsuranceFund {
+    STATE.with(|s| {
+        let state = s.borrow();
+        let subaccount = state.insurance_subaccount.expect("The insurance subaccount is created in init");
+        let insurance = &state.insurance;
+        InsuranceFund {
+            address: AccountIdentifier::new(&ic_cdk::id(), &subaccount).to_string(),
+            balance: insurance.balance.map(|(balance, _)| balance),
+            balance_updated_at: insurance.balance.map(|(_, at)| at),
+            contributed: insurance.contributed,
+            paid_out: insurance.paid_out,
+        }
+    })
+}
+
+// Anyone may call this, e.g. after topping up the fund
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn refresh_insurance_balance() -> StakingResult<u64> {
+    let subaccount = STATE.with(|s| s.borrow_mut().get_insurance_subaccount());
+    read_balance(subaccount).await
+        .ok_or_else(|| StakingError::TransferFailed("Failed to check insurance balance".to_string()))
+}
@@ -1,5 +1,4 @@
-
-use candid::{candid_method, Principal, CandidType, Deserialize};
+use candid::{candid_method, Nat, Principal, CandidType, Deserialize};
 use ic_cdk::api::time;
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade};
 use ic_ledger_types::{
@@ -7,9 +6,25 @@ use ic_ledger_types::{
     MAINNET_LEDGER_CANISTER_ID, TransferArgs, AccountBalanceArgs,
 };
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+mod allocation;
+mod certification;
+mod events;
+mod governance;
+mod icrc3;
+mod icrc7;
+mod insurance;
+mod memory;
+mod metrics;
+mod reconcile;
+mod rewards;
+mod slash_proposals;
+mod slashing;
+mod snapshots;
+mod timelock;
 mod types;
+mod vesting;
 use types::*;
 
 thread_local! {
@@ -23,6 +38,121 @@ struct State {
     next_subaccount_id: u64,
     pending_deposits: HashMap<Subaccount, PendingDeposit>, 
     reward_subaccount: Option<Subaccount>, 
+    slash_subaccount: Option<Subaccount>,
+    insurance_subaccount: Option<Subaccount>,
+    treasury: Option<Principal>,
+    next_deposit_id: u64,
+    position_transfers: HashMap<u64, PositionTransfer>,
+    busy_deposits: HashSet<u64>,
+    distribution_in_progress: bool,
+    claims_in_flight: u64,
+    icrc7_tx_count: u64,
+    // ICRC-7 transfers with `created_at_time`, by that time and `icrc7::transfer_hash`
+    icrc7_recent: BTreeMap<(u64, [u8; 32]), u64>,
+    admins: HashSet<Principal>,
+    pause_status: PauseStatus,
+    emergency_mode: bool,
+    slash_config: SlashConfig,
+    slash_proposals: BTreeMap<u64, SlashProposal>,
+    next_slash_proposal_id: u64,
+    timelock: timelock::Timelock,
+    parameters: PoolParameters,
+    governance: governance::Governance,
+    reward_timer: Option<ic_cdk_timers::TimerId>,
+    snapshots: BTreeMap<u64, snapshots::Snapshot>,
+    slash_history: BTreeMap<u64, SlashRecord>,
+    counters: metrics::Counters,
+    reward_carry: HashMap<u64, u64>, // deposit id -> reward dust awaiting a payable amount
+    unclaimed_rewards: HashMap<Principal, u64>, // vested by positions that have since closed
+    stake_clocks: HashMap<u64, rewards::StakeClock>, // deposits whose amount changed this epoch
+    last_distribution: u64,
+    vesting: BTreeMap<u64, Vec<VestingGrant>>, // by deposit id
+    next_vesting_id: u64,
+    claiming_grants: HashSet<u64>, // grant ids with a claim in flight, see `vesting::grant`
+    insurance: insurance::Insurance,
+}
+
+// Marks deposits as having a ledger operation in flight, so they cannot be moved,
+// split or withdrawn concurrently. Released when dropped.
+struct DepositGuard {
+    deposit_ids: Vec<u64>,
+}
+
+impl DepositGuard {
+    fn new(deposit_ids: &[u64]) -> StakingResult<Self> {
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            if deposit_ids.iter().any(|id| state.busy_deposits.contains(id)) {
+                return Err(StakingError::DepositBusy);
+            }
+            state.busy_deposits.extend(deposit_ids.iter().copied());
+            Ok(DepositGuard { deposit_ids: deposit_ids.to_vec() })
+        })
+    }
+}
+
+impl Drop for DepositGuard {
+    fn drop(&mut self) {
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            for id in &self.deposit_ids {
+                state.busy_deposits.remove(id);
+            }
+        });
+    }
+}
+
+// Marks a reward distribution as running, so a second one cannot start while the first
+// is waiting on the ledger. Released when dropped.
+struct DistributionGuard;
+
+impl DistributionGuard {
+    fn new() -> StakingResult<Self> {
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            if state.distribution_in_progress {
+                return Err(StakingError::DistributionInProgress);
+            }
+            if state.claims_in_flight > 0 {
+                return Err(StakingError::ClaimInProgress);
+            }
+            state.distribution_in_progress = true;
+            Ok(DistributionGuard)
+        })
+    }
+}
+
+impl Drop for DistributionGuard {
+    fn drop(&mut self) {
+        STATE.with(|s| s.borrow_mut().distribution_in_progress = false);
+    }
+}
+
+// Marks a reward claim as waiting on the ledger. A claim takes its rewards off the
+// records before the transfer, so a distribution running alongside it would count
+// those funds as free. Claims and distributions therefore exclude each other.
+struct ClaimGuard;
+
+impl ClaimGuard {
+    fn new() -> StakingResult<Self> {
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            if state.distribution_in_progress {
+                return Err(StakingError::DistributionInProgress);
+            }
+            state.claims_in_flight += 1;
+            Ok(ClaimGuard)
+        })
+    }
+}
+
+impl Drop for ClaimGuard {
+    fn drop(&mut self) {
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            state.claims_in_flight = state.claims_in_flight.saturating_sub(1);
+        });
+    }
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -44,12 +174,49 @@ impl State {
         })
     }
 
+    fn find_deposit(&self, deposit_id: u64) -> Option<(Principal, usize)> {
+        self.users.iter().find_map(|(owner, user_deposits)| {
+            user_deposits.deposits.iter()
+                .position(|d| d.id == deposit_id)
+                .map(|index| (*owner, index))
+        })
+    }
+
+    fn move_deposit(&mut self, deposit_id: u64, from: &Principal, to: &Principal) -> StakingResult<()> {
+        if self.busy_deposits.contains(&deposit_id) {
+            return Err(StakingError::DepositBusy);
+        }
+        let user_deposits = self.users.get_mut(from).ok_or(StakingError::DepositNotFound)?;
+        let index = user_deposits.deposits.iter()
+            .position(|d| d.id == deposit_id)
+            .ok_or(StakingError::DepositNotFound)?;
+        let deposit = user_deposits.deposits.remove(index);
+        self.get_user_deposits_mut(to).deposits.push(deposit);
+        self.position_transfers.remove(&deposit_id);
+        certification::certify_user(self, from);
+        certification::certify_user(self, to);
+        events::record(*from, EventKind::PositionTransferred { deposit_id, to: *to });
+        Ok(())
+    }
+
+    fn get_deposit_mut(&mut self, owner: &Principal, deposit_id: u64) -> Option<&mut Deposit> {
+        self.users.get_mut(owner)?.deposits.iter_mut().find(|d| d.id == deposit_id)
+    }
+
+    // Fold the deposit's stake-seconds so far into its clock. Call before its amount changes.
+    fn settle_stake(&mut self, owner: &Principal, deposit_id: u64) {
+        let now = time();
+        let Some(deposit) = self.get_user_deposits(owner).and_then(|ud| ud.deposits.iter().find(|d| d.id == deposit_id)) else {
+            return;
+        };
+        let accumulated = rewards::stake_time(self.stake_clocks.get(&deposit_id), deposit, self.last_distribution, now);
+        self.stake_clocks.insert(deposit_id, rewards::StakeClock { accumulated, since: now });
+    }
+
     fn generate_subaccount(&mut self) -> Subaccount {
-        let mut subaccount = [0u8; 32];
-        let id_bytes = self.next_subaccount_id.to_be_bytes();
-        subaccount[24..32].copy_from_slice(&id_bytes);
+        let subaccount = subaccount_for(self.next_subaccount_id);
         self.next_subaccount_id += 1;
-        Subaccount(subaccount)
+        subaccount
     }
 
     fn get_reward_subaccount(&mut self) -> Subaccount {
@@ -61,10 +228,65 @@ impl State {
             subaccount
         }
     }
+
+    fn get_slash_subaccount(&mut self) -> Subaccount {
+        if let Some(subaccount) = self.slash_subaccount {
+            subaccount
+        } else {
+            let subaccount = self.generate_subaccount();
+            self.slash_subaccount = Some(subaccount);
+            subaccount
+        }
+    }
+
+    fn get_insurance_subaccount(&mut self) -> Subaccount {
+        if let Some(subaccount) = self.insurance_subaccount {
+            subaccount
+        } else {
+            let subaccount = self.generate_subaccount();
+            self.insurance_subaccount = Some(subaccount);
+            subaccount
+        }
+    }
+}
+
+// Subaccounts are numbered in the order they were handed out
+fn subaccount_for(id: u64) -> Subaccount {
+    let mut subaccount = [0u8; 32];
+    subaccount[24..32].copy_from_slice(&id.to_be_bytes());
+    Subaccount(subaccount)
+}
+
+// Controllers are always admins; other principals can be added by a controller
+fn require_admin() -> StakingResult<()> {
+    let caller = ic_cdk::caller();
+    let is_admin = ic_cdk::api::is_controller(&caller)
+        || STATE.with(|s| s.borrow().admins.contains(&caller));
+    if is_admin { Ok(()) } else { Err(StakingError::Unauthorized) }
+}
+
+fn require_controller() -> StakingResult<()> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(StakingError::Unauthorized)
+    }
+}
+
+fn check_not_paused(scope: PauseScope) -> StakingResult<()> {
+    if STATE.with(|s| s.borrow().pause_status.is_paused(scope)) {
+        Err(StakingError::Paused)
+    } else {
+        Ok(())
+    }
 }
 
 #[init]
 fn init() {
+    // Created up front, so `get_insurance_fund` can show its address without taking a
+    // subaccount that the next deposit intention would otherwise be given
+    STATE.with(|s| s.borrow_mut().get_insurance_subaccount());
+    certification::certify();
     ic_cdk::println!("Staking pool canister initialized");
 }
 
@@ -72,6 +294,7 @@ fn init() {
 #[ic_cdk::update]
 #[candid_method(update)]
 async fn create_deposit_intention(args: DepositArgs) -> StakingResult<DepositIntention> {
+    check_not_paused(PauseScope::Deposits)?;
     let caller = ic_cdk::caller();
     
     if args.amount == 0 {
@@ -89,9 +312,15 @@ async fn create_deposit_intention(args: DepositArgs) -> StakingResult<DepositInt
         created_time: time(),
     };
 
+    let lock_period = pending_deposit.lock_period;
     STATE.with(|s| {
         s.borrow_mut().pending_deposits.insert(subaccount, pending_deposit);
     });
+    events::record(caller, EventKind::DepositIntentionCreated {
+        subaccount,
+        expected_amount: args.amount,
+        lock_period,
+    });
 
     let canister_id = ic_cdk::id();
     let deposit_address = AccountIdentifier::new(&canister_id, &subaccount);
@@ -108,6 +337,7 @@ async fn create_deposit_intention(args: DepositArgs) -> StakingResult<DepositInt
 #[ic_cdk::update]
 #[candid_method(update)]
 async fn confirm_deposit(subaccount: Subaccount) -> StakingResult<()> {
+    check_not_paused(PauseScope::Deposits)?;
     let caller = ic_cdk::caller();
     
     // Get pending deposit info
@@ -127,6 +357,7 @@ async fn confirm_deposit(subaccount: Subaccount) -> StakingResult<()> {
         STATE.with(|s| {
             s.borrow_mut().pending_deposits.remove(&subaccount);
         });
+        events::record(caller, EventKind::DepositIntentionExpired { subaccount });
         return Err(StakingError::DepositExpired);
     }
 
@@ -145,21 +376,31 @@ async fn confirm_deposit(subaccount: Subaccount) -> StakingResult<()> {
         return Err(StakingError::InsufficientFunds);
     }
 
-    // Create confirmed deposit record
-    let deposit = Deposit {
-        amount: balance, // Use actual balance received
-        deposit_time: current_time,
-        lock_period: pending_deposit.lock_period,
-        subaccount,
-    };
-
     // Store deposit and update state
-    STATE.with(|s| {
+    let deposit_id = STATE.with(|s| {
         let mut state = s.borrow_mut();
+        let deposit_id = state.next_deposit_id;
+        let deposit = Deposit {
+            id: deposit_id,
+            amount: balance, // Use actual balance received
+            deposit_time: current_time,
+            lock_period: pending_deposit.lock_period,
+            subaccount,
+            rewards: 0,
+            auto_compound: false,
+        };
+        state.next_deposit_id += 1;
         let user_deposits = state.get_user_deposits_mut(&caller);
         user_deposits.deposits.push(deposit);
         state.total_staked += balance;
         state.pending_deposits.remove(&subaccount); // Clean up pending deposit
+        certification::certify_user(&state, &caller);
+        deposit_id
+    });
+    events::record(caller, EventKind::DepositConfirmed {
+        deposit_id,
+        amount: balance,
+        lock_period: pending_deposit.lock_period,
     });
 
     Ok(())
@@ -168,31 +409,31 @@ async fn confirm_deposit(subaccount: Subaccount) -> StakingResult<()> {
 #[ic_cdk::update]
 #[candid_method(update)]
 async fn withdraw(args: WithdrawArgs) -> StakingResult<u64> {
+    check_not_paused(PauseScope::Withdrawals)?;
     let caller = ic_cdk::caller();
     let current_time = time();
     
-    let (amount, subaccount, can_withdraw) = STATE.with(|s| {
+    let (amount, subaccount, deposit_id, can_withdraw) = STATE.with(|s| {
         let state = s.borrow();
         match state.get_user_deposits(&caller) {
             Some(user_deposits) => {
                 if args.deposit_index >= user_deposits.deposits.len() {
-                    return (0, Subaccount([0u8; 32]), Err(StakingError::DepositNotFound));
+                    return (0, Subaccount([0u8; 32]), 0, Err(StakingError::DepositNotFound));
                 }
                 
                 let deposit = &user_deposits.deposits[args.deposit_index];
-                let unlock_time = deposit.deposit_time + deposit.lock_period;
-                
-                if current_time < unlock_time {
-                    (0, Subaccount([0u8; 32]), Err(StakingError::LockPeriodNotExpired))
+                if current_time < deposit.unlock_time() {
+                    (0, Subaccount([0u8; 32]), 0, Err(StakingError::LockPeriodNotExpired))
                 } else {
-                    (deposit.amount, deposit.subaccount, Ok(()))
+                    (deposit.amount, deposit.subaccount, deposit.id, Ok(()))
                 }
             }
-            None => (0, Subaccount([0u8; 32]), Err(StakingError::DepositNotFound)),
+            None => (0, Subaccount([0u8; 32]), 0, Err(StakingError::DepositNotFound)),
         }
     });
 
     can_withdraw?;
+    let _guard = DepositGuard::new(&[deposit_id])?;
 
     // Transfer funds from deposit subaccount back to user
     let user_account = AccountIdentifier::new(&caller, &DEFAULT_SUBACCOUNT);
@@ -206,33 +447,333 @@ async fn withdraw(args: WithdrawArgs) -> StakingResult<u64> {
     };
 
     match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
-        Ok(Ok(_block_height)) => {
-            // Remove deposit after successful transfer
-            STATE.with(|s| {
+        Ok(Ok(block_height)) => {
+            // Remove deposit after successful transfer. Look it up by subaccount again,
+            // since the index may have shifted while the transfer was in flight.
+            let forfeited = STATE.with(|s| {
                 let mut state = s.borrow_mut();
+                let mut forfeited = 0;
                 if let Some(user_deposits) = state.users.get_mut(&caller) {
-                    user_deposits.deposits.remove(args.deposit_index);
-                    state.total_staked = state.total_staked.saturating_sub(amount);
+                    if let Some(index) = user_deposits.deposits.iter().position(|d| d.subaccount == subaccount) {
+                        let deposit = user_deposits.deposits.remove(index);
+                        state.position_transfers.remove(&deposit.id);
+                        state.stake_clocks.remove(&deposit.id);
+                        state.total_staked = state.total_staked.saturating_sub(amount);
+                        // Vested rewards stay claimable through `claim_vested`
+                        let (vested, unvested) = vesting::release(&mut state, deposit.id);
+                        if vested > 0 {
+                            *state.unclaimed_rewards.entry(caller).or_default() += vested;
+                        }
+                        forfeited = unvested;
+                    }
                 }
+                certification::certify_user(&state, &caller);
+                forfeited
+            });
+            events::record(caller, EventKind::Withdrawal {
+                deposit_id,
+                amount: amount.saturating_sub(DEFAULT_FEE.e8s()),
+                block_index: block_height,
             });
+            if forfeited > 0 {
+                events::record(caller, EventKind::RewardsForfeited { deposit_id, amount: forfeited });
+            }
             Ok(amount.saturating_sub(DEFAULT_FEE.e8s()))
         }
-        Ok(Err(transfer_error)) => Err(StakingError::TransferFailed(format!("{:?}", transfer_error))),
-        Err((code, msg)) => Err(StakingError::TransferFailed(format!("Call failed: {} - {}", code as u8, msg))),
+        Ok(Err(transfer_error)) => {
+            metrics::record_failed_transfer();
+            Err(StakingError::TransferFailed(format!("{:?}", transfer_error)))
+        }
+        Err((code, msg)) => {
+            metrics::record_failed_transfer();
+            Err(StakingError::TransferFailed(format!("Call failed: {} - {}", code as u8, msg)))
+        }
+    }
+}
+
+// Recover principal during an incident. Only available in emergency mode; ignores the
+// lock period and pays out whatever the deposit subaccount actually holds on the ledger
+// (minus the transfer fee) rather than the recorded amount. Unpaid rewards are forfeited.
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn emergency_withdraw(deposit_id: u64) -> StakingResult<u64> {
+    let caller = ic_cdk::caller();
+
+    if !STATE.with(|s| s.borrow().emergency_mode) {
+        return Err(StakingError::EmergencyModeInactive);
+    }
+
+    let deposit = STATE.with(|s| {
+        let state = s.borrow();
+        state.get_user_deposits(&caller)
+            .and_then(|ud| ud.deposits.iter().find(|d| d.id == deposit_id).cloned())
+    }).ok_or(StakingError::DepositNotFound)?;
+
+    let _guard = DepositGuard::new(&[deposit_id])?;
+
+    let canister_id = ic_cdk::id();
+    let account = AccountIdentifier::new(&canister_id, &deposit.subaccount);
+    let balance = match ic_ledger_types::account_balance(MAINNET_LEDGER_CANISTER_ID, AccountBalanceArgs { account }).await {
+        Ok(balance) => balance.e8s(),
+        Err(_) => return Err(StakingError::TransferFailed("Failed to check balance".to_string())),
+    };
+
+    if balance <= DEFAULT_FEE.e8s() {
+        return Err(StakingError::InsufficientFunds);
+    }
+
+    let payout = balance - DEFAULT_FEE.e8s();
+    let user_account = AccountIdentifier::new(&caller, &DEFAULT_SUBACCOUNT);
+    let block_index = transfer_from_subaccount(deposit.subaccount, user_account, payout, 5).await?; // Emergency memo
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        if let Some(user_deposits) = state.users.get_mut(&caller) {
+            user_deposits.deposits.retain(|d| d.id != deposit_id);
+        }
+        state.position_transfers.remove(&deposit_id);
+        state.stake_clocks.remove(&deposit_id);
+        state.vesting.remove(&deposit_id);
+        state.total_staked = state.total_staked.saturating_sub(deposit.amount);
+        certification::certify_user(&state, &caller);
+    });
+    events::record(caller, EventKind::EmergencyWithdrawal { deposit_id, amount: payout, block_index });
+
+    Ok(payout)
+}
+
+// Transfer `amount` out of one of the canister's subaccounts, paying the ledger fee on top
+async fn transfer_from_subaccount(
+    from_subaccount: Subaccount,
+    to: AccountIdentifier,
+    amount: u64,
+    memo: u64,
+) -> StakingResult<u64> {
+    let transfer_args = TransferArgs {
+        memo: ic_ledger_types::Memo(memo),
+        amount: Tokens::from_e8s(amount),
+        fee: DEFAULT_FEE,
+        from_subaccount: Some(from_subaccount),
+        to,
+        created_at_time: None,
+    };
+
+    match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
+        Ok(Ok(block_height)) => Ok(block_height),
+        Ok(Err(transfer_error)) => {
+            metrics::record_failed_transfer();
+            Err(StakingError::TransferFailed(format!("{:?}", transfer_error)))
+        }
+        Err((code, msg)) => {
+            metrics::record_failed_transfer();
+            Err(StakingError::TransferFailed(format!("Call failed: {} - {}", code as u8, msg)))
+        }
+    }
+}
+
+// Carve new positions out of an existing deposit. Each entry in `amounts` becomes a new
+// position with the same deposit time and lock period, funded by a ledger transfer from
+// the original subaccount. The original keeps the remainder and pays one fee per split.
+// Returns the ids of the new positions. If a transfer fails after some positions were
+// created, they are kept and returned along with the error.
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn split_position(deposit_id: u64, amounts: Vec<u64>) -> StakingResult<SplitResult> {
+    check_not_paused(PauseScope::Withdrawals)?;
+    let caller = ic_cdk::caller();
+    let fee = DEFAULT_FEE.e8s();
+
+    if amounts.is_empty() || amounts.iter().any(|&amount| amount <= fee) {
+        return Err(StakingError::InvalidAmount);
+    }
+
+    let deposit = STATE.with(|s| {
+        let state = s.borrow();
+        state.get_user_deposits(&caller)
+            .and_then(|ud| ud.deposits.iter().find(|d| d.id == deposit_id).cloned())
+    }).ok_or(StakingError::DepositNotFound)?;
+
+    // The original position must keep a non-zero balance after all splits and fees
+    let required = amounts.iter()
+        .try_fold(0u64, |acc, &amount| acc.checked_add(amount)?.checked_add(fee))
+        .ok_or(StakingError::InvalidAmount)?;
+    if required >= deposit.amount {
+        return Err(StakingError::InsufficientFunds);
+    }
+
+    let _guard = DepositGuard::new(&[deposit_id])?;
+    let canister_id = ic_cdk::id();
+    let mut new_ids = Vec::with_capacity(amounts.len());
+
+    for amount in amounts {
+        let subaccount = STATE.with(|s| s.borrow_mut().generate_subaccount());
+        let to = AccountIdentifier::new(&canister_id, &subaccount);
+
+        let block_index = match transfer_from_subaccount(deposit.subaccount, to, amount, 3).await { // Split memo
+            Ok(block_index) => block_index,
+            Err(e) if new_ids.is_empty() => return Err(e),
+            Err(e) => return Ok(SplitResult { new_deposit_ids: new_ids, failed: Some(e) }),
+        };
+
+        let new_id = STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            state.settle_stake(&caller, deposit_id);
+            if let Some(original) = state.get_deposit_mut(&caller, deposit_id) {
+                original.amount = original.amount.saturating_sub(amount + fee);
+            }
+            let new_id = state.next_deposit_id;
+            state.next_deposit_id += 1;
+            // The original position has been credited with this stake so far
+            state.stake_clocks.insert(new_id, rewards::StakeClock { accumulated: 0, since: time() });
+            state.get_user_deposits_mut(&caller).deposits.push(Deposit {
+                id: new_id,
+                amount,
+                deposit_time: deposit.deposit_time,
+                lock_period: deposit.lock_period,
+                subaccount,
+                rewards: 0,
+                auto_compound: deposit.auto_compound,
+            });
+            state.total_staked = state.total_staked.saturating_sub(fee);
+            certification::certify_user(&state, &caller);
+            new_id
+        });
+        events::record(caller, EventKind::PositionSplit { deposit_id, new_deposit_id: new_id, amount, block_index });
+        new_ids.push(new_id);
+    }
+
+    Ok(SplitResult { new_deposit_ids: new_ids, failed: None })
+}
+
+// Combine positions with the same lock period into one. Balances are moved into the
+// position that unlocks last, so no merged stake ends up unlocking earlier than before.
+// Each merged position pays one ledger fee. Returns the id of the surviving position and
+// the positions merged into it. If a transfer fails after some positions were merged,
+// they stay merged and the error is returned along with them.
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn merge_positions(deposit_ids: Vec<u64>) -> StakingResult<MergeResult> {
+    check_not_paused(PauseScope::Withdrawals)?;
+    let caller = ic_cdk::caller();
+    let fee = DEFAULT_FEE.e8s();
+
+    let unique: HashSet<u64> = deposit_ids.iter().copied().collect();
+    if deposit_ids.len() < 2 || unique.len() != deposit_ids.len() {
+        return Err(StakingError::InvalidAmount);
+    }
+
+    let deposits: Vec<Deposit> = STATE.with(|s| {
+        let state = s.borrow();
+        let user_deposits = state.get_user_deposits(&caller)?;
+        deposit_ids.iter()
+            .map(|id| user_deposits.deposits.iter().find(|d| d.id == *id).cloned())
+            .collect::<Option<Vec<_>>>()
+    }).ok_or(StakingError::DepositNotFound)?;
+
+    let lock_period = deposits[0].lock_period;
+    if deposits.iter().any(|d| d.lock_period != lock_period) {
+        return Err(StakingError::IncompatiblePositions);
+    }
+    if deposits.iter().any(|d| d.amount <= fee) {
+        return Err(StakingError::InsufficientFunds);
+    }
+
+    let target = deposits.iter()
+        .max_by_key(|d| (d.deposit_time, d.id))
+        .cloned()
+        .ok_or(StakingError::DepositNotFound)?;
+
+    let _guard = DepositGuard::new(&deposit_ids)?;
+    let canister_id = ic_cdk::id();
+    let target_account = AccountIdentifier::new(&canister_id, &target.subaccount);
+    let mut merged_ids = Vec::with_capacity(deposits.len() - 1);
+
+    for source in deposits.iter().filter(|d| d.id != target.id) {
+        let amount = source.amount - fee;
+
+        let block_index = match transfer_from_subaccount(source.subaccount, target_account, amount, 4).await { // Merge memo
+            Ok(block_index) => block_index,
+            Err(e) if merged_ids.is_empty() => return Err(e),
+            Err(e) => return Ok(MergeResult { deposit_id: target.id, merged_deposit_ids: merged_ids, failed: Some(e) }),
+        };
+
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            state.settle_stake(&caller, source.id);
+            state.settle_stake(&caller, target.id);
+            let source_clock = state.stake_clocks.remove(&source.id);
+            if let (Some(source_clock), Some(target_clock)) = (source_clock, state.stake_clocks.get_mut(&target.id)) {
+                target_clock.accumulated = target_clock.accumulated.saturating_add(source_clock.accumulated);
+            }
+            let mut rewards = 0;
+            if let Some(user_deposits) = state.users.get_mut(&caller) {
+                if let Some(index) = user_deposits.deposits.iter().position(|d| d.id == source.id) {
+                    rewards = user_deposits.deposits.remove(index).rewards;
+                }
+            }
+            state.position_transfers.remove(&source.id);
+            state.governance.inherit_votes(source.id, target.id);
+            vesting::transfer(&mut state, source.id, target.id);
+            if let Some(merged) = state.get_deposit_mut(&caller, target.id) {
+                merged.amount = merged.amount.saturating_add(amount);
+                merged.rewards = merged.rewards.saturating_add(rewards);
+            }
+            state.total_staked = state.total_staked.saturating_sub(fee);
+            certification::certify_user(&state, &caller);
+        });
+        events::record(caller, EventKind::PositionMerged {
+            deposit_id: source.id,
+            into_deposit_id: target.id,
+            amount,
+            block_index,
+        });
+        merged_ids.push(source.id);
+    }
+
+    Ok(MergeResult { deposit_id: target.id, merged_deposit_ids: merged_ids, failed: None })
+}
+
+// Move `cut` out of the reward subaccount, transfer fee included. Returns the amount
+// that arrived, or None if the cut does not cover the fee or the transfer failed.
+async fn skim_rewards(reward_subaccount: Subaccount, to: AccountIdentifier, cut: u64) -> Option<u64> {
+    if cut <= DEFAULT_FEE.e8s() {
+        return None;
+    }
+    let transfer_args = TransferArgs {
+        memo: ic_ledger_types::Memo(1), // Reward memo
+        amount: Tokens::from_e8s(cut - DEFAULT_FEE.e8s()),
+        fee: DEFAULT_FEE,
+        from_subaccount: Some(reward_subaccount),
+        to,
+        created_at_time: None,
+    };
+    match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
+        Ok(Ok(_)) => Some(cut - DEFAULT_FEE.e8s()),
+        Ok(Err(_)) | Err(_) => {
+            metrics::record_failed_transfer();
+            None
+        }
     }
 }
 
-//  Now properly transfers ICP from reward subaccount to distribute rewards
+// Distributes the reward subaccount's balance to deposits, weighted by lock tier. The
+// protocol fee (`protocol_fee_bps`) goes to the treasury first, then `insurance_bps` of
+// the rest to the insurance fund. A cut whose transfer fails stays with the stakers for
+// this epoch. Each transfer's fee comes out of the recipient's share; shares too small
+// to cover it are carried forward, see `rewards`. Only one distribution runs at a time,
+// and not while a claim is waiting on the ledger.
 #[ic_cdk::update]
 #[candid_method(update)]
-async fn reward_pool() -> StakingResult<u64> {
+async fn reward_pool() -> StakingResult<RewardReport> {
+    check_not_paused(PauseScope::Rewards)?;
+    let _guard = DistributionGuard::new()?;
     let (reward_subaccount, total_staked) = STATE.with(|s| {
         let mut state = s.borrow_mut();
         (state.get_reward_subaccount(), state.total_staked)
     });
 
     if total_staked == 0 {
-        return Ok(0);
+        return Ok(RewardReport::default());
     }
 
     // Check balance in reward subaccount
@@ -245,135 +786,719 @@ async fn reward_pool() -> StakingResult<u64> {
         Err(_) => return Err(StakingError::TransferFailed("Failed to check reward balance".to_string())),
     };
 
+    STATE.with(|s| s.borrow_mut().counters.reward_balance = Some((reward_balance, time())));
+
     if reward_balance <= DEFAULT_FEE.e8s() {
         return Err(StakingError::InsufficientFunds);
     }
 
-    let reward_amount = reward_balance.saturating_sub(DEFAULT_FEE.e8s());
+    // Dust carried for deposits that no longer exist is released back into the pool.
+    // Accrued rewards stay reserved until claimed or forfeited.
+    let (owners, candidates, claimable) = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let live: HashSet<u64> = state.users.values().flat_map(|ud| ud.deposits.iter().map(|d| d.id)).collect();
+        state.reward_carry.retain(|id, _| live.contains(id));
 
-    // Distribute rewards proportionally to each deposit subaccount
-    let mut total_distributed = 0u64;
-    
-    let user_deposits_clone = STATE.with(|s| {
-        s.borrow().users.clone()
+        let now = time();
+        let multipliers = &state.parameters.tier_multipliers;
+        let mut owners = HashMap::new();
+        let mut candidates = Vec::new();
+        for (owner, user_deposits) in &state.users {
+            for deposit in &user_deposits.deposits {
+                owners.insert(deposit.id, *owner);
+                let clock = state.stake_clocks.get(&deposit.id);
+                let stake_seconds = rewards::stake_time(clock, deposit, state.last_distribution, now) / 1_000_000_000;
+                candidates.push(rewards::Candidate {
+                    deposit_id: deposit.id,
+                    weight: stake_seconds * multipliers.for_lock_period(deposit.lock_period) as u128,
+                    carried: state.reward_carry.get(&deposit.id).copied().unwrap_or(0),
+                    // Compounded rewards only stay locked until the position matures
+                    compound: deposit.auto_compound && now < deposit.unlock_time(),
+                });
+            }
+        }
+        let claimable: u64 = state.users.values()
+            .flat_map(|ud| ud.deposits.iter().map(|d| d.rewards))
+            .chain(state.unclaimed_rewards.values().copied())
+            .sum();
+
+        // The next epoch starts now
+        state.stake_clocks.clear();
+        state.last_distribution = now;
+        (owners, candidates, claimable)
     });
 
-    for (user, user_deposits) in user_deposits_clone.iter() {
-        for deposit in &user_deposits.deposits {
-            let user_reward = (deposit.amount as u128 * reward_amount as u128 / total_staked as u128) as u64;
-            
-            if user_reward > 0 {
-                // Transfer reward to user's deposit subaccount
-                let deposit_account = AccountIdentifier::new(&canister_id, &deposit.subaccount);
-                let transfer_args = TransferArgs {
-                    memo: ic_ledger_types::Memo(1), // Reward memo
-                    amount: Tokens::from_e8s(user_reward),
-                    fee: DEFAULT_FEE,
-                    from_subaccount: Some(reward_subaccount),
-                    to: deposit_account,
-                    created_at_time: None,
-                };
-
-                match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
-                    Ok(Ok(_)) => {
-                        total_distributed += user_reward;
-                        // Update deposit amount in state
-                        STATE.with(|s| {
-                            let mut state = s.borrow_mut();
-                            if let Some(user_deposits_mut) = state.users.get_mut(user) {
-                                for deposit_mut in &mut user_deposits_mut.deposits {
-                                    if deposit_mut.subaccount == deposit.subaccount {
-                                        deposit_mut.amount = deposit_mut.amount.saturating_add(user_reward);
-                                        break;
-                                    }
-                                }
-                            }
-                        });
-                    }
-                    Ok(Err(_)) | Err(_) => {
-                        // Continue with other users if one transfer fails
-                        continue;
-                    }
+    let carried_total: u64 = candidates.iter().map(|c| c.carried).sum();
+    let mut available = reward_balance.saturating_sub(carried_total).saturating_sub(claimable);
+    let mut report = RewardReport { reward_balance, ..Default::default() };
+
+    let (protocol_fee_bps, treasury, insurance_bps, insurance_subaccount) = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let parameters = &state.parameters;
+        (parameters.protocol_fee_bps, state.treasury, parameters.insurance_bps, state.get_insurance_subaccount())
+    });
+
+    if let Some(treasury) = treasury {
+        let fee_cut = (available as u128 * protocol_fee_bps as u128 / 10_000) as u64;
+        let to = AccountIdentifier::new(&treasury, &DEFAULT_SUBACCOUNT);
+        if let Some(sent) = skim_rewards(reward_subaccount, to, fee_cut).await {
+            available -= fee_cut;
+            report.protocol_fee = sent;
+            report.fees += DEFAULT_FEE.e8s();
+            STATE.with(|s| s.borrow_mut().counters.protocol_fees += sent);
+        }
+    }
+
+    let insurance_cut = (available as u128 * insurance_bps as u128 / 10_000) as u64;
+    let to = AccountIdentifier::new(&canister_id, &insurance_subaccount);
+    if let Some(sent) = skim_rewards(reward_subaccount, to, insurance_cut).await {
+        available -= insurance_cut;
+        report.to_insurance = sent;
+        report.fees += DEFAULT_FEE.e8s();
+        STATE.with(|s| s.borrow_mut().insurance.contributed += sent);
+        insurance::adjust_balance(sent, 0);
+    }
+
+    let mut credited = 0;
+    for allocation in rewards::plan(available, DEFAULT_FEE.e8s(), &candidates) {
+        let gross = allocation.gross();
+        // Positions may have moved or closed while earlier transfers were in flight
+        let current = STATE.with(|s| {
+            let state = s.borrow();
+            let (owner, index) = state.find_deposit(allocation.deposit_id)?;
+            Some((owner, state.users[&owner].deposits[index].subaccount))
+        });
+        let (owner, outcome) = match current {
+            None => (owners[&allocation.deposit_id], RewardOutcome::Failed("Position closed".to_string())),
+            Some((owner, _)) if !allocation.compound => {
+                // Stays in the reward subaccount until claimed
+                if gross > 0 {
+                    report.accrued += gross;
+                    STATE.with(|s| {
+                        let mut state = s.borrow_mut();
+                        state.counters.rewards_distributed += gross;
+                        state.reward_carry.remove(&allocation.deposit_id);
+                        if let Some(deposit) = state.get_deposit_mut(&owner, allocation.deposit_id) {
+                            deposit.rewards = deposit.rewards.saturating_add(gross);
+                            vesting::grant(&mut state, allocation.deposit_id, gross);
+                        }
+                        certification::certify_user(&state, &owner);
+                    });
+                    events::record(owner, EventKind::RewardAccrued { deposit_id: allocation.deposit_id, amount: gross });
                 }
+                (owner, RewardOutcome::Accrued)
             }
+            Some((owner, _)) if !allocation.pay => (owner, RewardOutcome::Skipped),
+            // Held for the transfer, so the position can't move or close before it is credited
+            Some((owner, subaccount)) => match DepositGuard::new(&[allocation.deposit_id]) {
+                Err(_) => (owner, RewardOutcome::Skipped),
+                Ok(_guard) => {
+                    let reward = gross - DEFAULT_FEE.e8s();
+                    let transfer_args = TransferArgs {
+                        memo: ic_ledger_types::Memo(1), // Reward memo
+                        amount: Tokens::from_e8s(reward),
+                        fee: DEFAULT_FEE,
+                        from_subaccount: Some(reward_subaccount),
+                        to: AccountIdentifier::new(&canister_id, &subaccount),
+                        created_at_time: None,
+                    };
+
+                    let outcome = match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
+                        Ok(Ok(block_index)) => {
+                            report.distributed += reward;
+                            report.fees += DEFAULT_FEE.e8s();
+                            STATE.with(|s| {
+                                let mut state = s.borrow_mut();
+                                state.counters.rewards_distributed += reward;
+                                state.reward_carry.remove(&allocation.deposit_id);
+                                state.settle_stake(&owner, allocation.deposit_id);
+                                if let Some(deposit) = state.get_deposit_mut(&owner, allocation.deposit_id) {
+                                    deposit.amount = deposit.amount.saturating_add(reward);
+                                    credited += reward;
+                                }
+                                certification::certify_user(&state, &owner);
+                            });
+                            events::record(owner, EventKind::RewardPaid {
+                                deposit_id: allocation.deposit_id,
+                                amount: reward,
+                                block_index,
+                            });
+                            RewardOutcome::Paid { block_index }
+                        }
+                        Ok(Err(e)) => {
+                            metrics::record_failed_transfer();
+                            RewardOutcome::Failed(format!("{:?}", e))
+                        }
+                        Err((code, msg)) => {
+                            metrics::record_failed_transfer();
+                            RewardOutcome::Failed(format!("Call failed: {} - {}", code as u8, msg))
+                        }
+                    };
+                    (owner, outcome)
+                }
+            },
+        };
+
+        // Nothing left the subaccount for this deposit, so its whole amount stays reserved
+        if matches!(outcome, RewardOutcome::Skipped | RewardOutcome::Failed(_)) && gross > 0 {
+            report.carried_forward += gross;
+            STATE.with(|s| s.borrow_mut().reward_carry.insert(allocation.deposit_id, gross));
         }
+        report.allocations.push(RewardAllocation {
+            deposit_id: allocation.deposit_id,
+            owner,
+            share: allocation.share,
+            carried_in: allocation.carried,
+            outcome,
+        });
     }
 
-    // Update total staked
+    // Only what reached a position counts as stake
     STATE.with(|s| {
-        s.borrow_mut().total_staked = s.borrow().total_staked.saturating_add(total_distributed);
+        let mut state = s.borrow_mut();
+        state.total_staked = state.total_staked.saturating_add(credited);
+        certification::certify_total(&state);
     });
 
-    Ok(total_distributed)
+    Ok(report)
 }
 
+// Pay out the vested rewards of one position. Rewards are not locked, so the position
+// doesn't need to have matured.
 #[ic_cdk::update]
 #[candid_method(update)]
-async fn slash_pool(amount: u64, receiver: Principal) -> StakingResult<u64> {
+async fn claim_rewards(deposit_id: Option<u64>) -> StakingResult<RewardClaim> {
+    claim(ic_cdk::caller(), deposit_id).await
+}
+
+// Pay out the vested rewards of every position the caller holds, plus rewards left by
+// positions already withdrawn
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn claim_vested() -> StakingResult<RewardClaim> {
+    claim(ic_cdk::caller(), None).await
+}
+
+// The claim is paid in one transfer from the reward subaccount, minus one ledger fee
+async fn claim(caller: Principal, deposit_id: Option<u64>) -> StakingResult<RewardClaim> {
+    check_not_paused(PauseScope::Withdrawals)?;
+    let _guard = ClaimGuard::new()?;
+
+    // Taken out of the records before the transfer, so a concurrent claim can't pay twice
+    let (claimed, unclaimed, reward_subaccount) = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let now = time();
+        let deposit_ids: Vec<u64> = state.get_user_deposits(&caller)
+            .map(|ud| ud.deposits.iter().map(|d| d.id).filter(|id| deposit_id.is_none() || deposit_id == Some(*id)).collect())
+            .unwrap_or_default();
+        if deposit_id.is_some() && deposit_ids.is_empty() {
+            return Err(StakingError::DepositNotFound);
+        }
+        let mut claimed = Vec::new();
+        for id in deposit_ids {
+            let taken = vesting::take_vested(&mut state, id, now);
+            let amount: u64 = taken.iter().map(|(_, amount)| amount).sum();
+            if amount > 0 {
+                if let Some(deposit) = state.get_deposit_mut(&caller, id) {
+                    deposit.rewards = deposit.rewards.saturating_sub(amount);
+                }
+                claimed.push((id, amount, taken));
+            }
+        }
+        let unclaimed = match deposit_id {
+            None => state.unclaimed_rewards.remove(&caller).unwrap_or(0),
+            Some(_) => 0,
+        };
+        certification::certify_user(&state, &caller);
+        Ok((claimed, unclaimed, state.get_reward_subaccount()))
+    })?;
+
+    let total = claimed.iter().map(|(_, amount, _)| amount).sum::<u64>() + unclaimed;
+    let result = if total <= DEFAULT_FEE.e8s() {
+        Err(StakingError::InsufficientFunds)
+    } else {
+        let user_account = AccountIdentifier::new(&caller, &DEFAULT_SUBACCOUNT);
+        transfer_from_subaccount(reward_subaccount, user_account, total - DEFAULT_FEE.e8s(), 6).await // Claim memo
+    };
+
+    let block_index = match result {
+        Ok(block_index) => block_index,
+        Err(e) => {
+            // Give the rewards back to whoever holds the positions now
+            STATE.with(|s| {
+                let mut state = s.borrow_mut();
+                let mut closed = unclaimed;
+                for (_, _, taken) in &claimed {
+                    closed += vesting::restore(&mut state, taken);
+                }
+                if closed > 0 {
+                    *state.unclaimed_rewards.entry(caller).or_default() += closed;
+                }
+            });
+            return Err(e);
+        }
+    };
+
+    let deposit_ids: Vec<u64> = claimed.iter().map(|(id, _, _)| *id).collect();
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        for (_, _, taken) in &claimed {
+            vesting::finish_claim(&mut state, taken);
+        }
+        vesting::prune(&mut state, &deposit_ids);
+    });
+    let amount = total - DEFAULT_FEE.e8s();
+    events::record(caller, EventKind::RewardsClaimed { deposit_ids: deposit_ids.clone(), amount, block_index });
+    Ok(RewardClaim { deposit_ids, amount, fee: DEFAULT_FEE.e8s(), block_index })
+}
+
+// Choose whether a position's future rewards are compounded into it (and stay locked
+// with it) or accrue as vesting rewards. Rewards accrued so far keep vesting. A matured
+// position can't start compounding, since its rewards would be withdrawable at once;
+// one that matures while compounding accrues from then on.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn set_auto_compound(deposit_id: u64, enabled: bool) -> StakingResult<()> {
+    check_not_paused(PauseScope::All)?;
+    let caller = ic_cdk::caller();
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let deposit = state.get_deposit_mut(&caller, deposit_id).ok_or(StakingError::DepositNotFound)?;
+        if enabled && time() >= deposit.unlock_time() {
+            return Err(StakingError::InvalidArgument("Matured positions can't compound".to_string()));
+        }
+        deposit.auto_compound = enabled;
+        certification::certify_user(&state, &caller);
+        Ok(())
+    })?;
+    events::record(caller, EventKind::AutoCompoundSet { deposit_id, enabled });
+    Ok(())
+}
+
+// Only reachable through an approved slash proposal, see `slash_proposals`. The
+// insurance fund pays what it can first. For the rest, only the deposits `target`
+// covers are slashed, in proportion to their amounts. They pay their deductions into
+// the slash subaccount, which then pays the receiver in one transfer; see `slashing`
+// for how fees are shared out. Funds that could not be delivered stay in the slash
+// subaccount, recorded as held on the slash until `deliver_held_slash` sends them.
+async fn slash_pool(
+    proposal_id: u64,
+    amount: u64,
+    receiver: Principal,
+    reason: String,
+    target: SlashTarget,
+) -> StakingResult<SlashReport> {
+    check_not_paused(PauseScope::Slashing)?;
     if amount == 0 {
         return Err(StakingError::InvalidAmount);
     }
 
-    let total_staked = STATE.with(|s| s.borrow().total_staked);
-    if total_staked == 0 || amount > total_staked {
+    // Hold the targeted deposits until the slash is done, so none of them is withdrawn,
+    // split, merged or transferred while the ledger is called. Deposits busy with
+    // another ledger operation are left out of the slash.
+    let (targeted, slash_subaccount, insurance_subaccount) = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let targeted: BTreeMap<u64, Principal> = state.users.iter()
+            .flat_map(|(owner, ud)| {
+                ud.deposits.iter()
+                    .filter(|d| target.includes(owner, d))
+                    .map(move |d| (d.id, *owner))
+            })
+            .collect();
+        (targeted, state.get_slash_subaccount(), state.get_insurance_subaccount())
+    });
+    let mut guards = Vec::with_capacity(targeted.len());
+    let mut busy = Vec::new();
+    for (&deposit_id, &owner) in &targeted {
+        match DepositGuard::new(&[deposit_id]) {
+            Ok(guard) => guards.push((deposit_id, guard)),
+            Err(_) => busy.push((deposit_id, owner)),
+        }
+    }
+
+    let insurance_balance = insurance::read_balance(insurance_subaccount).await
+        .ok_or_else(|| StakingError::TransferFailed("Failed to check insurance balance".to_string()))?;
+    let from_insurance = amount.min(insurance_balance.saturating_sub(DEFAULT_FEE.e8s()));
+    let remainder = amount - from_insurance;
+
+    // Read the held deposits after the await, in deposit id order
+    let mut deposits: BTreeMap<u64, (Principal, Deposit)> = STATE.with(|s| {
+        let state = s.borrow();
+        guards.iter()
+            .filter_map(|(deposit_id, _)| {
+                let (owner, index) = state.find_deposit(*deposit_id)?;
+                Some((*deposit_id, (owner, state.users[&owner].deposits[index].clone())))
+            })
+            .collect()
+    });
+
+    // The fund pays first, so the deposits only have to cover what it cannot
+    let exposed: u64 = deposits.values().map(|(_, d)| d.amount).sum();
+    if remainder > exposed {
         return Err(StakingError::InsufficientFunds);
     }
 
-    let mut total_slashed = 0u64;
- 
+    // Planned before anything moves, so a slash the deposits cannot cover fails cleanly
+    let amounts = deposits.iter().map(|(id, (_, d))| (*id, d.amount)).collect();
+    let deductions = if remainder == 0 {
+        // The fund covers the whole slash and no deposit is touched
+        deposits.clear();
+        busy.clear();
+        BTreeMap::new()
+    } else {
+        slashing::plan(remainder, DEFAULT_FEE.e8s(), &amounts)?
+    };
 
-    // Collect all deposits to slash
-    let user_deposits_clone = STATE.with(|s| s.borrow().users.clone());
-    
-    // Slash deposits proportionally by transferring from each deposit subaccount
-    for (user, user_deposits) in user_deposits_clone.iter() {
-        for deposit in &user_deposits.deposits {
-            let slash_amount = (deposit.amount as u128 * amount as u128 / total_staked as u128) as u64;
-            
-            if slash_amount > DEFAULT_FEE.e8s() {
-                let transfer_amount = slash_amount.saturating_sub(DEFAULT_FEE.e8s());
-                let receiver_account = AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT);
-                
-                let transfer_args = TransferArgs {
-                    memo: ic_ledger_types::Memo(2), // Slash memo
-                    amount: Tokens::from_e8s(transfer_amount),
-                    fee: DEFAULT_FEE,
-                    from_subaccount: Some(deposit.subaccount),
-                    to: receiver_account,
-                    created_at_time: None,
-                };
-
-                match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
-                    Ok(Ok(_)) => {
-                        total_slashed += slash_amount;
-                        // Update deposit amount in state
-                        STATE.with(|s| {
-                            let mut state = s.borrow_mut();
-                            if let Some(user_deposits_mut) = state.users.get_mut(user) {
-                                for deposit_mut in &mut user_deposits_mut.deposits {
-                                    if deposit_mut.subaccount == deposit.subaccount {
-                                        deposit_mut.amount = deposit_mut.amount.saturating_sub(slash_amount);
-                                        break;
-                                    }
-                                }
-                            }
-                        });
-                    }
-                    Ok(Err(_)) | Err(_) => {
-                        // Continue with other deposits if one transfer fails
-                        continue;
+    let canister_id = ic_cdk::id();
+    let mut report = SlashReport {
+        slash_id: 0,
+        amount,
+        receiver,
+        from_insurance: 0,
+        insurance_block_index: None,
+        total_deducted: 0,
+        fees: 0,
+        sent_to_receiver: 0,
+        receiver_block_index: None,
+        held: 0,
+        deductions: Vec::new(),
+    };
+
+    if from_insurance > 0 {
+        let transfer_args = TransferArgs {
+            memo: ic_ledger_types::Memo(2), // Slash memo
+            amount: Tokens::from_e8s(from_insurance),
+            fee: DEFAULT_FEE,
+            from_subaccount: Some(insurance_subaccount),
+            to: AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT),
+            created_at_time: None,
+        };
+        match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
+            Ok(Ok(block_index)) => {
+                report.from_insurance = from_insurance;
+                report.insurance_block_index = Some(block_index);
+                report.fees += DEFAULT_FEE.e8s();
+                STATE.with(|s| s.borrow_mut().insurance.paid_out += from_insurance);
+                insurance::adjust_balance(0, from_insurance + DEFAULT_FEE.e8s());
+            }
+            // Nothing has been deducted yet, so the slash can simply be retried
+            Ok(Err(e)) => {
+                metrics::record_failed_transfer();
+                return Err(StakingError::TransferFailed(format!("{:?}", e)));
+            }
+            Err((code, msg)) => {
+                metrics::record_failed_transfer();
+                return Err(StakingError::TransferFailed(format!("Call failed: {} - {}", code as u8, msg)));
+            }
+        }
+    }
+
+    for (deposit_id, owner) in busy {
+        report.deductions.push(SlashDeduction {
+            deposit_id,
+            owner,
+            deducted: 0,
+            fee: 0,
+            outcome: SlashOutcome::Failed("Deposit busy".to_string()),
+        });
+    }
+
+    for (deposit_id, (user, deposit)) in &deposits {
+        let Some(&deducted) = deductions.get(deposit_id) else {
+            report.deductions.push(SlashDeduction {
+                deposit_id: *deposit_id,
+                owner: *user,
+                deducted: 0,
+                fee: 0,
+                outcome: SlashOutcome::Exempt,
+            });
+            continue;
+        };
+
+        let transfer_args = TransferArgs {
+            memo: ic_ledger_types::Memo(2), // Slash memo
+            amount: Tokens::from_e8s(deducted - DEFAULT_FEE.e8s()),
+            fee: DEFAULT_FEE,
+            from_subaccount: Some(deposit.subaccount),
+            to: AccountIdentifier::new(&canister_id, &slash_subaccount),
+            created_at_time: None,
+        };
+
+        let outcome = match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
+            Ok(Ok(block_index)) => {
+                STATE.with(|s| {
+                    let mut state = s.borrow_mut();
+                    state.counters.slashed += deducted;
+                    state.settle_stake(user, *deposit_id);
+                    if let Some(deposit_mut) = state.get_deposit_mut(user, *deposit_id) {
+                        deposit_mut.amount = deposit_mut.amount.saturating_sub(deducted);
                     }
-                }
+                    certification::certify_user(&state, user);
+                });
+                events::record(*user, EventKind::Slashed {
+                    deposit_id: *deposit_id,
+                    amount: deducted,
+                    receiver,
+                    block_index,
+                });
+                SlashOutcome::Collected { block_index }
+            }
+            Ok(Err(e)) => {
+                metrics::record_failed_transfer();
+                SlashOutcome::Failed(format!("{:?}", e))
             }
+            Err((code, msg)) => {
+                metrics::record_failed_transfer();
+                SlashOutcome::Failed(format!("Call failed: {} - {}", code as u8, msg))
+            }
+        };
+
+        let collected = matches!(outcome, SlashOutcome::Collected { .. });
+        if collected {
+            report.total_deducted += deducted;
+            report.fees += DEFAULT_FEE.e8s();
+            report.held += deducted - DEFAULT_FEE.e8s();
         }
+        report.deductions.push(SlashDeduction {
+            deposit_id: *deposit_id,
+            owner: *user,
+            deducted: if collected { deducted } else { 0 },
+            fee: if collected { DEFAULT_FEE.e8s() } else { 0 },
+            outcome,
+        });
     }
 
+    // Every deduction is settled; the transfer to the receiver no longer involves them
+    drop(guards);
+
     // Update total staked
     STATE.with(|s| {
-        s.borrow_mut().total_staked = s.borrow().total_staked.saturating_sub(total_slashed);
+        let mut state = s.borrow_mut();
+        state.total_staked = state.total_staked.saturating_sub(report.total_deducted);
+        certification::certify_total(&state);
     });
 
-    Ok(total_slashed)
+    if report.held > DEFAULT_FEE.e8s() {
+        let transfer_args = TransferArgs {
+            memo: ic_ledger_types::Memo(2), // Slash memo
+            amount: Tokens::from_e8s(report.held - DEFAULT_FEE.e8s()),
+            fee: DEFAULT_FEE,
+            from_subaccount: Some(slash_subaccount),
+            to: AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT),
+            created_at_time: None,
+        };
+        match ic_ledger_types::transfer(MAINNET_LEDGER_CANISTER_ID, transfer_args).await {
+            Ok(Ok(block_index)) => {
+                report.sent_to_receiver = report.held - DEFAULT_FEE.e8s();
+                report.fees += DEFAULT_FEE.e8s();
+                report.receiver_block_index = Some(block_index);
+                report.held = 0;
+            }
+            Ok(Err(_)) | Err(_) => metrics::record_failed_transfer(),
+        }
+    }
+
+    report.slash_id = slashing::record(proposal_id, reason, target, &report);
+    Ok(report)
+}
+
+// Retry the transfer to the receiver for funds a slash collected but could not
+// deliver. Controller only. Returns the block index of the transfer.
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn deliver_held_slash(slash_id: u64) -> StakingResult<u64> {
+    require_controller()?;
+
+    // Taken off the record up front, so a second call cannot send the same funds
+    let (held, receiver, slash_subaccount) = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let slash_subaccount = state.get_slash_subaccount();
+        let record = state.slash_history.get_mut(&slash_id).ok_or(StakingError::InvalidArgument("Unknown slash".to_string()))?;
+        if record.held <= DEFAULT_FEE.e8s() {
+            return Err(StakingError::InsufficientFunds);
+        }
+        Ok((std::mem::take(&mut record.held), record.receiver, slash_subaccount))
+    })?;
+
+    let to = AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT);
+    match transfer_from_subaccount(slash_subaccount, to, held - DEFAULT_FEE.e8s(), 2).await { // Slash memo
+        Ok(block_index) => {
+            events::record(ic_cdk::caller(), EventKind::HeldSlashDelivered {
+                slash_id,
+                amount: held - DEFAULT_FEE.e8s(),
+                receiver,
+                block_index,
+            });
+            Ok(block_index)
+        }
+        Err(e) => {
+            STATE.with(|s| {
+                if let Some(record) = s.borrow_mut().slash_history.get_mut(&slash_id) {
+                    record.held = held;
+                }
+            });
+            Err(e)
+        }
+    }
+}
+
+// Hand a position over to another principal. The deposit keeps its subaccount and
+// lock terms; only the owner changes. With `require_acceptance` the position stays
+// with the caller until the recipient calls `accept_position`.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn transfer_position(args: TransferPositionArgs) -> StakingResult<()> {
+    check_not_paused(PauseScope::All)?;
+    let caller = ic_cdk::caller();
+
+    if args.new_owner == caller || args.new_owner == Principal::anonymous() {
+        return Err(StakingError::InvalidRecipient);
+    }
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        match state.find_deposit(args.deposit_id) {
+            Some((owner, _)) if owner == caller => {}
+            _ => return Err(StakingError::DepositNotFound),
+        }
+
+        if args.require_acceptance {
+            state.position_transfers.insert(args.deposit_id, PositionTransfer {
+                deposit_id: args.deposit_id,
+                from: caller,
+                to: args.new_owner,
+                created_time: time(),
+            });
+            events::record(caller, EventKind::PositionOffered { deposit_id: args.deposit_id, to: args.new_owner });
+            Ok(())
+        } else {
+            state.move_deposit(args.deposit_id, &caller, &args.new_owner)
+        }
+    })
+}
+
+// Accept a position offered via `transfer_position` with `require_acceptance`
+#[ic_cdk::update]
+#[candid_method(update)]
+fn accept_position(deposit_id: u64) -> StakingResult<()> {
+    check_not_paused(PauseScope::All)?;
+    let caller = ic_cdk::caller();
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let transfer = state.position_transfers.get(&deposit_id)
+            .cloned()
+            .ok_or(StakingError::DepositNotFound)?;
+
+        if transfer.to != caller {
+            return Err(StakingError::Unauthorized);
+        }
+
+        state.move_deposit(deposit_id, &transfer.from, &caller)
+    })
+}
+
+// Withdraw a pending position offer; only the current owner can cancel
+#[ic_cdk::update]
+#[candid_method(update)]
+fn cancel_position_transfer(deposit_id: u64) -> StakingResult<()> {
+    check_not_paused(PauseScope::All)?;
+    let caller = ic_cdk::caller();
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        match state.position_transfers.get(&deposit_id) {
+            Some(transfer) if transfer.from == caller => {
+                state.position_transfers.remove(&deposit_id);
+                events::record(caller, EventKind::PositionOfferCancelled { deposit_id });
+                Ok(())
+            }
+            Some(_) => Err(StakingError::Unauthorized),
+            None => Err(StakingError::DepositNotFound),
+        }
+    })
+}
+
+// Pending position offers sent or received by `user`
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_position_transfers(user: Principal) -> Vec<PositionTransfer> {
+    STATE.with(|s| {
+        s.borrow().position_transfers.values()
+            .filter(|t| t.from == user || t.to == user)
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+#[candid_method(update)]
+fn pause(scope: PauseScope) -> StakingResult<()> {
+    require_admin()?;
+    STATE.with(|s| s.borrow_mut().pause_status.set(scope, true));
+    ic_cdk::println!("Paused {:?} by {}", scope, ic_cdk::caller());
+    events::record(ic_cdk::caller(), EventKind::Paused { scope });
+    Ok(())
+}
+
+// Unpausing `All` resumes every scope
+#[ic_cdk::update]
+#[candid_method(update)]
+fn unpause(scope: PauseScope) -> StakingResult<()> {
+    require_admin()?;
+    STATE.with(|s| s.borrow_mut().pause_status.set(scope, false));
+    ic_cdk::println!("Unpaused {:?} by {}", scope, ic_cdk::caller());
+    events::record(ic_cdk::caller(), EventKind::Unpaused { scope });
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_pause_status() -> PauseStatus {
+    STATE.with(|s| s.borrow().pause_status.clone())
+}
+
+// Entering emergency mode also pauses every other operation
+#[ic_cdk::update]
+#[candid_method(update)]
+fn set_emergency_mode(enabled: bool) -> StakingResult<()> {
+    require_admin()?;
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        state.emergency_mode = enabled;
+        if enabled {
+            state.pause_status.set(PauseScope::All, true);
+        }
+    });
+    ic_cdk::println!("Emergency mode set to {} by {}", enabled, ic_cdk::caller());
+    events::record(ic_cdk::caller(), EventKind::EmergencyModeSet { enabled });
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_emergency_mode() -> bool {
+    STATE.with(|s| s.borrow().emergency_mode)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_admins() -> Vec<Principal> {
+    STATE.with(|s| s.borrow().admins.iter().copied().collect())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_protocol_fee() -> ProtocolFee {
+    STATE.with(|s| {
+        let state = s.borrow();
+        ProtocolFee {
+            fee_bps: state.parameters.protocol_fee_bps,
+            max_fee_bps: governance::MAX_PROTOCOL_FEE_BPS,
+            treasury: state.treasury,
+            collected: state.counters.protocol_fees,
+        }
+    })
 }
 
 // Get reward subaccount address for funding
@@ -393,19 +1518,26 @@ fn get_reward_address() -> String {
 #[ic_cdk::update]
 #[candid_method(update)]
 fn cleanup_expired_deposits() -> u64 {
+    // Only drops stale intentions, so it stays available in maintenance mode
     let current_time = time();
     let expiry_time = 15 * 60 * 1_000_000_000; // 15 minutes
     
-    STATE.with(|s| {
+    let expired = STATE.with(|s| {
         let mut state = s.borrow_mut();
-        let initial_count = state.pending_deposits.len();
-        
-        state.pending_deposits.retain(|_, deposit| {
-            current_time <= deposit.created_time + expiry_time
-        });
-        
-        (initial_count - state.pending_deposits.len()) as u64
-    })
+        let expired: Vec<(Subaccount, Principal)> = state.pending_deposits.iter()
+            .filter(|(_, deposit)| current_time > deposit.created_time + expiry_time)
+            .map(|(subaccount, deposit)| (*subaccount, deposit.user))
+            .collect();
+        for (subaccount, _) in &expired {
+            state.pending_deposits.remove(subaccount);
+        }
+        expired
+    });
+
+    for (subaccount, user) in &expired {
+        events::record(*user, EventKind::DepositIntentionExpired { subaccount: *subaccount });
+    }
+    expired.len() as u64
 }
 
 #[ic_cdk::query]
@@ -452,7 +1584,26 @@ fn pre_upgrade() {
 #[post_upgrade]
 fn post_upgrade() {
     // Deserialize state after upgrade
+    STATE.with(|s| s.borrow_mut().get_insurance_subaccount());
+    events::migrate();
+    events::rebuild_index();
+    icrc3::backfill_blocks();
+    certification::certify();
 }
 
 // Generate candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+#[cfg(test)]
+mod tests {
+    // Regenerate with `UPDATE_CANDID=1 cargo test -p staking_pool_backend --lib candid`
+    #[test]
+    fn candid_interface_is_up_to_date() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/staking_pool_backend.did");
+        let interface = super::__export_service();
+        if std::env::var("UPDATE_CANDID").is_ok() {
+            std::fs::write(path, &interface).expect("Failed to write the Candid interface");
+        }
+        let current = std::fs::read_to_string(path).expect("Failed to read the Candid interface");
+        assert!(current == interface, "staking_pool_backend.did is out of date, regenerate it with UPDATE_CANDID=1");
+    }
+}
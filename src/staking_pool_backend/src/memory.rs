@@ -0,0 +1,24 @@
+// Stable memory layout. Each stable structure gets its own virtual memory; ids must
+// never be reused or reordered, since the data behind them survives upgrades.
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub(crate) const EVENT_INDEX: MemoryId = MemoryId::new(0); // legacy event log, see `events::migrate`
+pub(crate) const EVENT_DATA: MemoryId = MemoryId::new(1); // legacy event log
+pub(crate) const BLOCK_HASHES: MemoryId = MemoryId::new(2);
+pub(crate) const ARCHIVES: MemoryId = MemoryId::new(3);
+pub(crate) const ARCHIVE_WASM: MemoryId = MemoryId::new(4);
+pub(crate) const EVENTS: MemoryId = MemoryId::new(5);
+pub(crate) const EVENT_COUNT: MemoryId = MemoryId::new(6);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub(crate) fn get(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
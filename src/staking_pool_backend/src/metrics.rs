@@ -0,0 +1,116 @@
+// Prometheus metrics served over `http_request` at /metrics, for scraping through the
+// boundary node. Counters are cumulative since the last upgrade.
+use candid::candid_method;
+use std::fmt::Write;
+
+use crate::types::*;
+use crate::STATE;
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub(crate) rewards_distributed: u64,
+    pub(crate) slashed: u64,
+    pub(crate) protocol_fees: u64,
+    pub(crate) failed_transfers: u64,
+    // Reward subaccount balance and when it was last read from the ledger
+    pub(crate) reward_balance: Option<(u64, u64)>,
+}
+
+pub(crate) fn record_failed_transfer() {
+    STATE.with(|s| s.borrow_mut().counters.failed_transfers += 1);
+}
+
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str, samples: &[(&str, u128)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+    out.push('\n');
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_memory_bytes() -> u128 {
+    core::arch::wasm32::memory_size(0) as u128 * 65536
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_memory_bytes() -> u128 {
+    0
+}
+
+fn encode_metrics() -> String {
+    let mut out = String::new();
+    STATE.with(|s| {
+        let state = s.borrow();
+        let mut tiers = TierBalances::default();
+        for deposit in state.users.values().flat_map(|ud| ud.deposits.iter()) {
+            tiers.add(deposit.lock_period, deposit.amount);
+        }
+        let depositors = state.users.values().filter(|ud| !ud.deposits.is_empty()).count();
+        let counters = &state.counters;
+
+        write_metric(&mut out, "staking_total_staked_e8s", "Total amount staked, in e8s", "gauge",
+            &[("", state.total_staked as u128)]);
+        write_metric(&mut out, "staking_tier_staked_e8s", "Amount staked per lock tier, in e8s", "gauge", &[
+            ("tier=\"90d\"", tiers.days90 as u128),
+            ("tier=\"180d\"", tiers.days180 as u128),
+            ("tier=\"360d\"", tiers.days360 as u128),
+            ("tier=\"other\"", tiers.other as u128),
+        ]);
+        write_metric(&mut out, "staking_depositors", "Principals with at least one position", "gauge",
+            &[("", depositors as u128)]);
+        write_metric(&mut out, "staking_pending_intentions", "Deposit intentions awaiting confirmation", "gauge",
+            &[("", state.pending_deposits.len() as u128)]);
+        if let Some((balance, read_at)) = counters.reward_balance {
+            write_metric(&mut out, "staking_reward_balance_e8s", "Reward subaccount balance at the last distribution, in e8s", "gauge",
+                &[("", balance as u128)]);
+            write_metric(&mut out, "staking_reward_balance_timestamp_seconds", "When the reward balance was last read", "gauge",
+                &[("", (read_at / 1_000_000_000) as u128)]);
+        }
+        write_metric(&mut out, "staking_rewards_distributed_e8s_total", "Rewards paid out to positions, in e8s", "counter",
+            &[("", counters.rewards_distributed as u128)]);
+        write_metric(&mut out, "staking_slashed_e8s_total", "Amount slashed from positions, in e8s", "counter",
+            &[("", counters.slashed as u128)]);
+        write_metric(&mut out, "staking_protocol_fees_e8s_total", "Protocol fees sent to the treasury, in e8s", "counter",
+            &[("", counters.protocol_fees as u128)]);
+        write_metric(&mut out, "staking_failed_transfers_total", "Ledger transfers that failed", "counter",
+            &[("", counters.failed_transfers as u128)]);
+    });
+
+    write_metric(&mut out, "staking_cycles_balance", "Cycles balance of the canister", "gauge",
+        &[("", ic_cdk::api::canister_balance128())]);
+    write_metric(&mut out, "staking_heap_memory_bytes", "Size of the wasm heap, in bytes", "gauge",
+        &[("", heap_memory_bytes())]);
+    write_metric(&mut out, "staking_stable_memory_bytes", "Size of stable memory, in bytes", "gauge",
+        &[("", ic_cdk::api::stable::stable64_size() as u128 * 65536)]);
+    out
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if request.method != "GET" || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"Not found".to_vec(),
+        };
+    }
+
+    let body = encode_metrics().into_bytes();
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), "text/plain; version=0.0.4".to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+            ("Cache-Control".to_string(), "no-store".to_string()),
+        ],
+        body,
+    }
+}
@@ -0,0 +1,169 @@
+// Checks internal records against the ledger. Every deposit subaccount should hold
+// exactly its recorded amount, and `total_staked` should equal the sum of deposits.
+// The reward subaccount should cover every reward still owed, and the slash subaccount
+// every slash that has not been delivered. Subaccounts that were handed out but are no
+// longer in use should be empty; any balance left there is reported as orphaned.
+use candid::{candid_method, Principal};
+use ic_ledger_types::{AccountBalanceArgs, AccountIdentifier, Subaccount, MAINNET_LEDGER_CANISTER_ID};
+use std::collections::HashSet;
+
+use crate::types::*;
+use crate::{certification, events, require_admin, subaccount_for, DepositGuard, STATE};
+
+pub(crate) async fn ledger_balance(subaccount: Subaccount) -> Option<u64> {
+    let account = AccountIdentifier::new(&ic_cdk::id(), &subaccount);
+    ic_ledger_types::account_balance(MAINNET_LEDGER_CANISTER_ID, AccountBalanceArgs { account })
+        .await
+        .ok()
+        .map(|balance| balance.e8s())
+}
+
+// Compare every deposit, the reward subaccount and all unused subaccounts with the
+// ledger. With `correct`, deposit amounts are set to their ledger balance and
+// `total_staked` to the sum of deposits. Corrections require rewards and slashing to
+// be paused, so no transfer into or out of a deposit is in flight while it is read.
+// Makes one ledger call per subaccount ever handed out.
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn reconcile(correct: bool) -> StakingResult<ReconciliationReport> {
+    require_admin()?;
+    if correct {
+        let quiet = STATE.with(|s| {
+            let pause_status = &s.borrow().pause_status;
+            pause_status.is_paused(PauseScope::Rewards) && pause_status.is_paused(PauseScope::Slashing)
+        });
+        if !quiet {
+            return Err(StakingError::InvalidArgument("Pause rewards and slashing before correcting records".to_string()));
+        }
+    }
+
+    let (total_staked, deposits, in_use, reward_subaccount, slash_subaccount, subaccount_count) = STATE.with(|s| {
+        let state = s.borrow();
+        let deposits: Vec<(Principal, Deposit)> = state.users.iter()
+            .flat_map(|(owner, ud)| ud.deposits.iter().map(move |d| (*owner, d.clone())))
+            .collect();
+        let mut in_use: HashSet<Subaccount> = deposits.iter().map(|(_, d)| d.subaccount).collect();
+        in_use.extend(state.pending_deposits.keys().copied());
+        in_use.extend(state.reward_subaccount);
+        in_use.extend(state.slash_subaccount);
+        in_use.extend(state.insurance_subaccount);
+        (state.total_staked, deposits, in_use, state.reward_subaccount, state.slash_subaccount, state.next_subaccount_id)
+    });
+
+    let mut report = ReconciliationReport {
+        total_staked,
+        sum_of_deposits: deposits.iter().map(|(_, d)| d.amount).sum(),
+        deposits_checked: 0,
+        mismatches: Vec::new(),
+        reward_balance: None,
+        reward_reserved: 0,
+        reward_shortfall: 0,
+        slash_balance: None,
+        slash_held: 0,
+        slash_shortfall: 0,
+        orphaned: Vec::new(),
+        failed_checks: Vec::new(),
+        corrected: correct,
+    };
+
+    for (owner, deposit) in deposits {
+        // Keeps withdrawals, splits and merges off the deposit while its balance is read
+        let Ok(_guard) = DepositGuard::new(&[deposit.id]) else {
+            report.failed_checks.push(deposit.subaccount);
+            continue;
+        };
+        let Some(ledger) = ledger_balance(deposit.subaccount).await else {
+            report.failed_checks.push(deposit.subaccount);
+            continue;
+        };
+        report.deposits_checked += 1;
+
+        let recorded = STATE.with(|s| {
+            s.borrow().get_user_deposits(&owner)
+                .and_then(|ud| ud.deposits.iter().find(|d| d.id == deposit.id))
+                .map(|d| d.amount)
+        });
+        let Some(recorded) = recorded else { continue };
+        if recorded == ledger {
+            continue;
+        }
+
+        report.mismatches.push(BalanceMismatch {
+            deposit_id: deposit.id,
+            owner,
+            subaccount: deposit.subaccount,
+            recorded,
+            ledger,
+        });
+        if correct {
+            STATE.with(|s| {
+                let mut state = s.borrow_mut();
+                state.settle_stake(&owner, deposit.id);
+                if let Some(d) = state.get_deposit_mut(&owner, deposit.id) {
+                    d.amount = ledger;
+                }
+                certification::certify_user(&state, &owner);
+            });
+            events::record(owner, EventKind::DepositCorrected { deposit_id: deposit.id, recorded, ledger });
+        }
+    }
+
+    if let Some(subaccount) = reward_subaccount {
+        report.reward_balance = ledger_balance(subaccount).await;
+        match report.reward_balance {
+            Some(balance) => {
+                report.reward_reserved = STATE.with(|s| {
+                    let state = s.borrow();
+                    state.users.values()
+                        .flat_map(|ud| ud.deposits.iter().map(|d| d.rewards))
+                        .chain(state.unclaimed_rewards.values().copied())
+                        .chain(state.reward_carry.values().copied())
+                        .sum()
+                });
+                report.reward_shortfall = report.reward_reserved.saturating_sub(balance);
+            }
+            None => report.failed_checks.push(subaccount),
+        }
+    }
+
+    if let Some(subaccount) = slash_subaccount {
+        report.slash_balance = ledger_balance(subaccount).await;
+        match report.slash_balance {
+            Some(balance) => {
+                report.slash_held = STATE.with(|s| s.borrow().slash_history.values().map(|r| r.held).sum());
+                report.slash_shortfall = report.slash_held.saturating_sub(balance);
+            }
+            None => report.failed_checks.push(subaccount),
+        }
+    }
+
+    for id in 0..subaccount_count {
+        let subaccount = subaccount_for(id);
+        if in_use.contains(&subaccount) {
+            continue;
+        }
+        match ledger_balance(subaccount).await {
+            Some(0) => {}
+            Some(balance) => report.orphaned.push(OrphanedSubaccount { subaccount, balance }),
+            None => report.failed_checks.push(subaccount),
+        }
+    }
+
+    if correct {
+        STATE.with(|s| {
+            let mut state = s.borrow_mut();
+            state.total_staked = state.users.values()
+                .flat_map(|ud| ud.deposits.iter())
+                .map(|d| d.amount)
+                .sum();
+            certification::certify_total(&state);
+        });
+    }
+
+    ic_cdk::println!(
+        "Reconciliation by {}: {} mismatches, {} orphaned subaccounts, {} failed checks, shortfalls of {} in rewards and {} in slashes",
+        ic_cdk::caller(), report.mismatches.len(), report.orphaned.len(), report.failed_checks.len(),
+        report.reward_shortfall, report.slash_shortfall
+    );
+    Ok(report)
+}
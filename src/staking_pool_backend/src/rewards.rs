@@ -0,0 +1,184 @@
+// Reward distribution math. Rewards normally accrue to a position and stay in the
+// reward subaccount until claimed, so distributing them costs nothing. Positions that
+// auto-compound and are still locked are paid into their deposit subaccount instead,
+// where the lock stands in for vesting. Every such transfer pays the ledger fee, so
+// each recipient's fee is taken out of its own share rather than out of a balance
+// that later transfers still depend on. A compounding deposit
+// whose share, together with any dust carried from earlier epochs, does not exceed the
+// fee is skipped; the amount stays in the reward subaccount, reserved for that deposit
+// in the next epoch.
+//
+// Shares are weighted by stake-seconds since the previous distribution rather than by
+// the balance at distribution time, so stake that arrives late in an epoch earns only
+// for the time it was actually staked.
+use std::collections::BTreeMap;
+
+use crate::allocation::allocate;
+use crate::types::Deposit;
+
+// A deposit's stake-seconds (in e8s times nanoseconds) up to `since`. Deposits without
+// a clock have held their current amount since they were made, or since the epoch began.
+#[derive(Clone, Copy)]
+pub(crate) struct StakeClock {
+    pub accumulated: u128,
+    pub since: u64,
+}
+
+pub(crate) fn stake_time(clock: Option<&StakeClock>, deposit: &Deposit, epoch_start: u64, now: u64) -> u128 {
+    let clock = clock.copied().unwrap_or(StakeClock {
+        accumulated: 0,
+        since: deposit.deposit_time.max(epoch_start),
+    });
+    clock.accumulated.saturating_add(deposit.amount as u128 * now.saturating_sub(clock.since) as u128)
+}
+
+pub(crate) struct Candidate {
+    pub deposit_id: u64,
+    pub weight: u128,
+    pub carried: u64,
+    pub compound: bool,
+}
+
+pub(crate) struct Allocation {
+    pub deposit_id: u64,
+    pub share: u64,
+    pub carried: u64,
+    pub compound: bool,
+    pub pay: bool, // compound with a transfer this epoch
+}
+
+impl Allocation {
+    // Share plus carried dust; a paid deposit receives this minus the fee
+    pub fn gross(&self) -> u64 {
+        self.share.saturating_add(self.carried)
+    }
+}
+
+// Split `available` over `candidates` by weight, in deposit id order. The gross
+// amounts of all allocations add up to exactly `available` plus the carried dust,
+// unless every weight is zero.
+pub(crate) fn plan(available: u64, fee: u64, candidates: &[Candidate]) -> Vec<Allocation> {
+    let weights: BTreeMap<u64, u128> = candidates.iter().map(|c| (c.deposit_id, c.weight)).collect();
+    let candidates: BTreeMap<u64, &Candidate> = candidates.iter().map(|c| (c.deposit_id, c)).collect();
+    allocate(available, &weights).into_iter()
+        .map(|(deposit_id, share)| {
+            let candidate = candidates[&deposit_id];
+            Allocation {
+                deposit_id,
+                share,
+                carried: candidate.carried,
+                compound: candidate.compound,
+                pay: candidate.compound && share.saturating_add(candidate.carried) > fee,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const FEE: u64 = 10_000;
+
+    // Weight, carried dust and compounding, by deposit id
+    type Inputs = BTreeMap<u64, (u128, u64, bool)>;
+
+    fn inputs() -> impl Strategy<Value = Inputs> {
+        prop::collection::btree_map(any::<u64>(), (0u128..1_000_000_000_000_000_000, 0u64..100_000, any::<bool>()), 0..50)
+    }
+
+    fn candidates(inputs: &Inputs) -> Vec<Candidate> {
+        inputs.iter()
+            .map(|(&deposit_id, &(weight, carried, compound))| Candidate { deposit_id, weight, carried, compound })
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn shares_never_exceed_available(available in 0u64..1_000_000_000_000, inputs in inputs()) {
+            let candidates = candidates(&inputs);
+            let allocations = plan(available, FEE, &candidates);
+            let shares: u64 = allocations.iter().map(|a| a.share).sum();
+            prop_assert!(shares <= available);
+            if candidates.iter().any(|c| c.weight > 0) {
+                prop_assert_eq!(shares, available);
+            }
+            let paid: u64 = allocations.iter().filter(|a| a.pay).map(|a| a.gross() - FEE).sum();
+            let carried: u64 = candidates.iter().map(|c| c.carried).sum();
+            prop_assert!(paid <= available + carried);
+        }
+
+        #[test]
+        fn pays_only_compounding_shares_above_fee(available in 0u64..1_000_000_000_000, inputs in inputs()) {
+            for allocation in plan(available, FEE, &candidates(&inputs)) {
+                let (_, carried, compound) = inputs[&allocation.deposit_id];
+                prop_assert_eq!(allocation.carried, carried);
+                prop_assert_eq!(allocation.compound, compound);
+                prop_assert_eq!(allocation.gross(), allocation.share + carried);
+                prop_assert_eq!(allocation.pay, compound && allocation.gross() > FEE);
+            }
+        }
+    }
+
+    #[test]
+    fn dust_is_carried_until_it_covers_the_fee() {
+        let candidate = |carried| Candidate { deposit_id: 7, weight: 1, carried, compound: true };
+
+        let first = plan(FEE, FEE, &[candidate(0)]);
+        assert_eq!((first[0].share, first[0].gross(), first[0].pay), (FEE, FEE, false));
+
+        let second = plan(1, FEE, &[candidate(first[0].gross())]);
+        assert_eq!((second[0].share, second[0].gross(), second[0].pay), (1, FEE + 1, true));
+    }
+
+    #[test]
+    fn accruing_shares_are_never_paid() {
+        let accrue = Candidate { deposit_id: 1, weight: 1, carried: 0, compound: false };
+        let compound = Candidate { deposit_id: 2, weight: 1, carried: 0, compound: true };
+        let allocations = plan(10 * FEE, FEE, &[accrue, compound]);
+        assert_eq!(allocations.len(), 2);
+        assert_eq!((allocations[0].share, allocations[0].pay), (5 * FEE, false));
+        assert_eq!((allocations[1].share, allocations[1].pay), (5 * FEE, true));
+    }
+
+    fn deposit(amount: u64, deposit_time: u64) -> Deposit {
+        Deposit {
+            id: 0,
+            amount,
+            deposit_time,
+            lock_period: 0,
+            subaccount: ic_ledger_types::Subaccount([0; 32]),
+            rewards: 0,
+            auto_compound: false,
+        }
+    }
+
+    #[test]
+    fn stake_time_is_zero_without_elapsed_time() {
+        assert_eq!(stake_time(None, &deposit(1_000, 500), 0, 500), 0);
+        assert_eq!(stake_time(None, &deposit(1_000, 500), 0, 400), 0);
+        let clock = StakeClock { accumulated: 42, since: 500 };
+        assert_eq!(stake_time(Some(&clock), &deposit(1_000, 0), 0, 500), 42);
+    }
+
+    #[test]
+    fn stake_time_counts_only_the_part_of_the_epoch_held() {
+        // Made halfway through the epoch, or before it began
+        assert_eq!(stake_time(None, &deposit(1_000, 500), 0, 1_000), 500_000);
+        assert_eq!(stake_time(None, &deposit(1_000, 0), 400, 1_000), 600_000);
+
+        // Resized at 700: earlier stake-seconds plus the new amount since then
+        let clock = StakeClock { accumulated: 300_000, since: 700 };
+        assert_eq!(stake_time(Some(&clock), &deposit(2_000, 0), 400, 1_000), 900_000);
+    }
+
+    #[test]
+    fn stake_time_does_not_overflow() {
+        let max = stake_time(None, &deposit(u64::MAX, 0), 0, u64::MAX);
+        assert_eq!(max, u64::MAX as u128 * u64::MAX as u128);
+
+        let clock = StakeClock { accumulated: u128::MAX - 1, since: 0 };
+        assert_eq!(stake_time(Some(&clock), &deposit(u64::MAX, 0), 0, u64::MAX), u128::MAX);
+    }
+}
@@ -0,0 +1,236 @@
+// Multi-signature flow for slashing. A signer proposes an amount, receiver and
+// justification; once `threshold` of the configured signers have approved, the slash
+// is queued in the timelock and executed after its delay. Proposals that don't reach
+// the threshold before they expire lapse.
+use candid::{candid_method, Principal};
+use ic_cdk::api::time;
+use ic_ledger_types::DEFAULT_FEE;
+
+use crate::types::*;
+use crate::{check_not_paused, events, slash_pool, slashing, timelock, STATE};
+
+const MAX_JUSTIFICATION_LENGTH: usize = 1024;
+const MAX_TARGETS: usize = 1000;
+
+fn require_signer(caller: &Principal) -> StakingResult<()> {
+    let is_signer = STATE.with(|s| s.borrow().slash_config.signers.contains(caller));
+    if is_signer { Ok(()) } else { Err(StakingError::Unauthorized) }
+}
+
+// Open proposals past their deadline, and queued ones whose timelock action has
+// expired, are reported as expired even before an update call gets to mark them
+fn with_effective_status(mut proposal: SlashProposal, timelock: &timelock::Timelock, now: u64) -> SlashProposal {
+    let expired = match proposal.status {
+        SlashProposalStatus::Open => now > proposal.expires_at,
+        SlashProposalStatus::Queued { action_id } => timelock.has_expired(action_id, now),
+        _ => false,
+    };
+    if expired {
+        proposal.status = SlashProposalStatus::Expired;
+    }
+    proposal
+}
+
+// Checked when a config change is queued and again when it is applied
+pub(crate) fn validate_slash_config(config: SlashConfig) -> StakingResult<SlashConfig> {
+    let mut signers = config.signers;
+    signers.sort();
+    signers.dedup();
+
+    if config.threshold == 0 || config.threshold as usize > signers.len() {
+        return Err(StakingError::InvalidArgument("Threshold must be between 1 and the number of signers".to_string()));
+    }
+    if config.proposal_ttl == 0 {
+        return Err(StakingError::InvalidArgument("Proposal TTL must be positive".to_string()));
+    }
+
+    Ok(SlashConfig {
+        signers,
+        threshold: config.threshold,
+        proposal_ttl: config.proposal_ttl,
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_slash_config() -> SlashConfig {
+    STATE.with(|s| s.borrow().slash_config.clone())
+}
+
+// Create a slash proposal. The proposer's own approval is counted, so with a
+// threshold of 1 the slash is queued immediately. Returns the proposal id.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn propose_slash(args: ProposeSlashArgs) -> StakingResult<u64> {
+    check_not_paused(PauseScope::Slashing)?;
+    let caller = ic_cdk::caller();
+    require_signer(&caller)?;
+
+    if args.amount == 0 {
+        return Err(StakingError::InvalidAmount);
+    }
+    if args.justification.trim().is_empty() || args.justification.len() > MAX_JUSTIFICATION_LENGTH {
+        return Err(StakingError::InvalidArgument("Justification must be 1 to 1024 bytes".to_string()));
+    }
+
+    let target = args.target.unwrap_or(SlashTarget::All);
+    match &target {
+        SlashTarget::DepositedBetween { from, to } if from > to => {
+            return Err(StakingError::InvalidArgument("Time window ends before it starts".to_string()));
+        }
+        SlashTarget::Deposits(ids) if ids.is_empty() || ids.len() > MAX_TARGETS => {
+            return Err(StakingError::InvalidArgument("Target 1 to 1000 deposits".to_string()));
+        }
+        SlashTarget::Principals(principals) if principals.is_empty() || principals.len() > MAX_TARGETS => {
+            return Err(StakingError::InvalidArgument("Target 1 to 1000 principals".to_string()));
+        }
+        _ => {}
+    }
+
+    // The insurance fund pays before any deposit is slashed. Its balance here is the last
+    // one read; `slash_pool` checks again against the ledger.
+    let covered = STATE.with(|s| {
+        let state = s.borrow();
+        let insurance = state.insurance.balance.map_or(0, |(balance, _)| balance.saturating_sub(DEFAULT_FEE.e8s()));
+        slashing::exposed_stake(&state.users, &target).saturating_add(insurance)
+    });
+    if args.amount > covered {
+        return Err(StakingError::InsufficientFunds);
+    }
+
+    let id = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let id = state.next_slash_proposal_id;
+        state.next_slash_proposal_id += 1;
+        let now = time();
+        let proposal = SlashProposal {
+            id,
+            proposer: caller,
+            amount: args.amount,
+            receiver: args.receiver,
+            justification: args.justification,
+            target,
+            approvals: vec![caller],
+            created_at: now,
+            expires_at: now.saturating_add(state.slash_config.proposal_ttl.saturating_mul(1_000_000_000)),
+            status: SlashProposalStatus::Open,
+        };
+        state.slash_proposals.insert(id, proposal);
+        id
+    });
+
+    events::record(caller, EventKind::SlashProposed { proposal_id: id, amount: args.amount, receiver: args.receiver });
+    try_queue(id, caller);
+    Ok(id)
+}
+
+// Approve an open proposal; queues the slash once the threshold is reached
+#[ic_cdk::update]
+#[candid_method(update)]
+fn approve_slash(proposal_id: u64) -> StakingResult<SlashProposalStatus> {
+    check_not_paused(PauseScope::Slashing)?;
+    let caller = ic_cdk::caller();
+    require_signer(&caller)?;
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let proposal = state.slash_proposals.get_mut(&proposal_id)
+            .ok_or(StakingError::ProposalNotFound)?;
+
+        if !matches!(proposal.status, SlashProposalStatus::Open) {
+            return Err(StakingError::ProposalNotOpen);
+        }
+        if time() > proposal.expires_at {
+            proposal.status = SlashProposalStatus::Expired;
+            return Err(StakingError::ProposalExpired);
+        }
+        if !proposal.approvals.contains(&caller) {
+            proposal.approvals.push(caller);
+        }
+        Ok(())
+    })?;
+    events::record(caller, EventKind::SlashApproved { proposal_id });
+
+    Ok(try_queue(proposal_id, caller))
+}
+
+// Queue the slash in the timelock if enough current signers have approved it.
+// Approvals from principals that have since been removed from the signer set don't count.
+fn try_queue(proposal_id: u64, caller: Principal) -> SlashProposalStatus {
+    let ready = STATE.with(|s| {
+        let state = s.borrow();
+        let config = &state.slash_config;
+        state.slash_proposals.get(&proposal_id).is_some_and(|proposal| {
+            let approvals = proposal.approvals.iter()
+                .filter(|p| config.signers.contains(p))
+                .count();
+            matches!(proposal.status, SlashProposalStatus::Open) && approvals >= config.threshold as usize
+        })
+    });
+
+    if ready {
+        let action_id = timelock::enqueue(AdminAction::ExecuteSlash { proposal_id }, caller);
+        set_status(proposal_id, SlashProposalStatus::Queued { action_id });
+    }
+
+    STATE.with(|s| {
+        s.borrow().slash_proposals.get(&proposal_id)
+            .map(|p| p.status.clone())
+            .unwrap_or(SlashProposalStatus::Expired)
+    })
+}
+
+pub(crate) fn set_status(proposal_id: u64, status: SlashProposalStatus) {
+    STATE.with(|s| {
+        if let Some(proposal) = s.borrow_mut().slash_proposals.get_mut(&proposal_id) {
+            proposal.status = status;
+        }
+    });
+}
+
+// Called by the timelock once the delay for an approved proposal has passed
+pub(crate) async fn execute_proposal(proposal_id: u64) -> StakingResult<()> {
+    let proposal = STATE.with(|s| {
+        s.borrow().slash_proposals.get(&proposal_id)
+            .filter(|p| matches!(p.status, SlashProposalStatus::Queued { .. }))
+            .cloned()
+    }).ok_or(StakingError::ProposalNotOpen)?;
+
+    set_status(proposal_id, SlashProposalStatus::Executing);
+
+    match slash_pool(proposal_id, proposal.amount, proposal.receiver, proposal.justification, proposal.target).await {
+        Ok(report) => {
+            set_status(proposal_id, SlashProposalStatus::Executed { report });
+            Ok(())
+        }
+        Err(e) => {
+            set_status(proposal_id, SlashProposalStatus::Failed { reason: format!("{:?}", e) });
+            Err(e)
+        }
+    }
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_slash_proposal(proposal_id: u64) -> Option<SlashProposal> {
+    let now = time();
+    STATE.with(|s| {
+        let state = s.borrow();
+        state.slash_proposals.get(&proposal_id)
+            .cloned()
+            .map(|p| with_effective_status(p, &state.timelock, now))
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_slash_proposals() -> Vec<SlashProposal> {
+    let now = time();
+    STATE.with(|s| {
+        let state = s.borrow();
+        state.slash_proposals.values()
+            .cloned()
+            .map(|p| with_effective_status(p, &state.timelock, now))
+            .collect()
+    })
+}
@@ -0,0 +1,143 @@
+// Slash accounting. Each slashed deposit moves its deduction into the slash
+// subaccount, paying one ledger fee, and the collected funds then go to the receiver
+// in a single transfer. Those fees are part of the deductions, so the receiver gets
+// the full slash amount: deductions add up to the amount plus one fee per transfer,
+// split in proportion to the deposits. A deposit whose deduction would not exceed the
+// fee cannot be slashed with a ledger transfer; it is exempt and its share falls on
+// the others. Every executed slash is kept in the slash history, so depositors can
+// see why their deposits shrank.
+use candid::{candid_method, Principal};
+use ic_cdk::api::time;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::allocation::allocate;
+use crate::types::*;
+use crate::STATE;
+
+const MAX_PAGE_SIZE: u64 = 1000;
+
+// Total amount of the deposits `target` covers
+pub(crate) fn exposed_stake(users: &HashMap<Principal, UserDeposits>, target: &SlashTarget) -> u64 {
+    users.iter()
+        .flat_map(|(owner, ud)| ud.deposits.iter().filter(move |d| target.includes(owner, d)))
+        .map(|d| d.amount)
+        .sum()
+}
+
+// Deductions by deposit id, given each deposit's amount. Exempt deposits are left out.
+pub(crate) fn plan(amount: u64, fee: u64, deposits: &BTreeMap<u64, u64>) -> StakingResult<BTreeMap<u64, u64>> {
+    let mut weights: BTreeMap<u64, u128> = deposits.iter()
+        .filter(|(_, amount)| **amount > 0)
+        .map(|(id, amount)| (*id, *amount as u128))
+        .collect();
+
+    loop {
+        // One transfer per slashed deposit, plus the one to the receiver
+        let needed = (weights.len() as u64 + 1).checked_mul(fee)
+            .and_then(|fees| fees.checked_add(amount))
+            .ok_or(StakingError::InsufficientFunds)?;
+        if weights.is_empty() || needed as u128 > weights.values().sum::<u128>() {
+            return Err(StakingError::InsufficientFunds);
+        }
+
+        let deductions = allocate(needed, &weights);
+        let exempt: Vec<u64> = deductions.iter()
+            .filter(|(_, deduction)| **deduction <= fee)
+            .map(|(id, _)| *id)
+            .collect();
+        if exempt.is_empty() {
+            return Ok(deductions);
+        }
+        for id in exempt {
+            weights.remove(&id);
+        }
+    }
+}
+
+// Store the outcome of a slash. Returns the slash id.
+pub(crate) fn record(proposal_id: u64, reason: String, target: SlashTarget, report: &SlashReport) -> u64 {
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let id = state.slash_history.last_key_value().map_or(0, |(id, _)| id + 1);
+        state.slash_history.insert(id, SlashRecord {
+            id,
+            proposal_id,
+            reason,
+            target,
+            from_insurance: report.from_insurance,
+            amount: report.total_deducted,
+            receiver: report.receiver,
+            held: report.held,
+            timestamp: time(),
+            deductions: report.deductions.iter().filter(|d| d.deducted > 0).cloned().collect(),
+        });
+        id
+    })
+}
+
+// Oldest first; `limit` is capped at 1000
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_slash_history(offset: u64, limit: u64) -> Vec<SlashRecord> {
+    STATE.with(|s| {
+        s.borrow().slash_history.values()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+// Every slash that took funds from the deposit, oldest first
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_deposit_slashes(deposit_id: u64) -> Vec<DepositSlash> {
+    STATE.with(|s| {
+        s.borrow().slash_history.values()
+            .flat_map(|record| {
+                record.deductions.iter()
+                    .filter(|d| d.deposit_id == deposit_id)
+                    .map(|d| DepositSlash {
+                        slash_id: record.id,
+                        timestamp: record.timestamp,
+                        reason: record.reason.clone(),
+                        deducted: d.deducted,
+                        fee: d.fee,
+                    })
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const FEE: u64 = 10_000;
+
+    proptest! {
+        #[test]
+        fn deductions_cover_amount_and_fees(
+            amount in 1u64..1_000_000_000_000,
+            deposits in prop::collection::btree_map(any::<u64>(), 0u64..10_000_000_000_000, 0..50),
+        ) {
+            if let Ok(deductions) = plan(amount, FEE, &deposits) {
+                let sum: u64 = deductions.values().sum();
+                prop_assert_eq!(sum, amount + FEE * (deductions.len() as u64 + 1));
+                for (id, deduction) in &deductions {
+                    prop_assert!(*deduction > FEE);
+                    prop_assert!(*deduction <= deposits[id]);
+                }
+            }
+        }
+
+        #[test]
+        fn fails_only_without_enough_stake(amount in 1u64..1_000_000_000_000, extra in 0u64..1_000_000) {
+            let deposits = BTreeMap::from([(0, amount + 2 * FEE + extra)]);
+            prop_assert_eq!(plan(amount, FEE, &deposits).ok(), Some(BTreeMap::from([(0, amount + 2 * FEE)])));
+            let short = BTreeMap::from([(0, amount + 2 * FEE - 1)]);
+            prop_assert!(plan(amount, FEE, &short).is_err());
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// Point-in-time copies of every principal's staked balance, e.g. for airdrops. A
+// snapshot is never updated after it is taken, so later deposits, withdrawals and
+// position transfers don't affect it.
+use candid::{candid_method, Principal};
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+use crate::types::*;
+use crate::{events, require_admin, STATE};
+
+const MAX_PAGE_SIZE: u64 = 1000;
+
+pub(crate) struct Snapshot {
+    taken_at: u64,
+    total_staked: u64,
+    // Keyed by principal so pages come back in a stable order
+    balances: BTreeMap<Principal, SnapshotEntry>,
+}
+
+impl Snapshot {
+    fn info(&self, id: u64) -> SnapshotInfo {
+        SnapshotInfo {
+            id,
+            taken_at: self.taken_at,
+            total_staked: self.total_staked,
+            holders: self.balances.len() as u64,
+        }
+    }
+}
+
+// Freeze the current staked balance of every principal. Returns the snapshot id.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn take_snapshot() -> StakingResult<u64> {
+    require_admin()?;
+
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let mut balances = BTreeMap::new();
+        for (owner, user_deposits) in state.users.iter() {
+            if user_deposits.deposits.is_empty() {
+                continue;
+            }
+            let mut entry = SnapshotEntry {
+                owner: *owner,
+                staked: 0,
+                by_tier: TierBalances::default(),
+            };
+            for deposit in &user_deposits.deposits {
+                entry.staked = entry.staked.saturating_add(deposit.amount);
+                entry.by_tier.add(deposit.lock_period, deposit.amount);
+            }
+            balances.insert(*owner, entry);
+        }
+
+        let id = state.snapshots.last_key_value().map_or(0, |(id, _)| id + 1);
+        let snapshot = Snapshot {
+            taken_at: time(),
+            total_staked: balances.values().map(|e: &SnapshotEntry| e.staked).sum(),
+            balances,
+        };
+        ic_cdk::println!("Snapshot {} taken with {} holders", id, snapshot.balances.len());
+        state.snapshots.insert(id, snapshot);
+        events::record(ic_cdk::caller(), EventKind::SnapshotTaken { snapshot_id: id });
+        Ok(id)
+    })
+}
+
+// Entries are ordered by principal; `limit` is capped at 1000
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_snapshot(id: u64, offset: u64, limit: u64) -> Option<SnapshotPage> {
+    STATE.with(|s| {
+        let state = s.borrow();
+        let snapshot = state.snapshots.get(&id)?;
+        let entries = snapshot.balances.values()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .cloned()
+            .collect();
+        Some(SnapshotPage { info: snapshot.info(id), entries })
+    })
+}
+
+// None if the snapshot doesn't exist; a zero balance if the principal had no stake
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_snapshot_balance(id: u64, principal: Principal) -> Option<SnapshotEntry> {
+    STATE.with(|s| {
+        let state = s.borrow();
+        let snapshot = state.snapshots.get(&id)?;
+        Some(snapshot.balances.get(&principal).cloned().unwrap_or(SnapshotEntry {
+            owner: principal,
+            staked: 0,
+            by_tier: TierBalances::default(),
+        }))
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_snapshots() -> Vec<SnapshotInfo> {
+    STATE.with(|s| {
+        s.borrow().snapshots.iter()
+            .map(|(id, snapshot)| snapshot.info(*id))
+            .collect()
+    })
+}
@@ -0,0 +1,242 @@
+// Timelock for sensitive admin actions. Queued actions become executable only after
+// the configured delay and are listed publicly, so stakers can exit before a change
+// they disagree with takes effect. Pausing and emergency mode are deliberately not
+// timelocked, since they must take effect immediately during an incident. Parameter
+// changes passed by governance are queued here as well.
+use candid::{candid_method, Principal};
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+use crate::types::*;
+use crate::{events, governance, require_admin, require_controller, slash_proposals, STATE};
+
+const DEFAULT_DELAY: u64 = 2 * 24 * 60 * 60; // 2 days in seconds
+const MIN_DELAY: u64 = 60 * 60; // 1 hour in seconds
+const GRACE_PERIOD: u64 = 14 * 24 * 60 * 60; // executable window after the delay, in seconds
+
+pub(crate) struct Timelock {
+    delay: u64, // in seconds
+    actions: BTreeMap<u64, QueuedAction>,
+    next_action_id: u64,
+}
+
+impl Default for Timelock {
+    fn default() -> Self {
+        Timelock {
+            delay: DEFAULT_DELAY,
+            actions: BTreeMap::new(),
+            next_action_id: 0,
+        }
+    }
+}
+
+impl Timelock {
+    // Whether a queued action can no longer run because its grace period has passed
+    pub(crate) fn has_expired(&self, action_id: u64, now: u64) -> bool {
+        self.actions.get(&action_id).is_some_and(|action| match action.status {
+            QueuedActionStatus::Expired => true,
+            QueuedActionStatus::Pending => now > action.expires_at,
+            _ => false,
+        })
+    }
+}
+
+pub(crate) fn enqueue(action: AdminAction, queued_by: Principal) -> u64 {
+    let id = STATE.with(|s| {
+        let timelock = &mut s.borrow_mut().timelock;
+        let id = timelock.next_action_id;
+        timelock.next_action_id += 1;
+
+        let now = time();
+        let executable_at = now.saturating_add(timelock.delay.saturating_mul(1_000_000_000));
+        timelock.actions.insert(id, QueuedAction {
+            id,
+            action,
+            queued_by,
+            queued_at: now,
+            executable_at,
+            expires_at: executable_at.saturating_add(GRACE_PERIOD * 1_000_000_000),
+            status: QueuedActionStatus::Pending,
+        });
+        id
+    });
+    let action = STATE.with(|s| s.borrow().timelock.actions[&id].action.clone());
+    events::record(queued_by, EventKind::AdminActionQueued { action_id: id, action });
+    id
+}
+
+fn validate(action: &AdminAction) -> StakingResult<()> {
+    match action {
+        AdminAction::AddAdmin(_) | AdminAction::RemoveAdmin(_) => Ok(()),
+        AdminAction::SetTreasury(treasury) if *treasury == Principal::anonymous() => Err(StakingError::InvalidRecipient),
+        AdminAction::SetTreasury(_) => Ok(()),
+        AdminAction::SetSlashConfig(config) => slash_proposals::validate_slash_config(config.clone()).map(|_| ()),
+        AdminAction::SetTimelockDelay(delay) if *delay < MIN_DELAY => {
+            Err(StakingError::InvalidArgument(format!("Delay must be at least {} seconds", MIN_DELAY)))
+        }
+        AdminAction::SetTimelockDelay(_) => Ok(()),
+        AdminAction::ChangeParameter(change) => governance::validate_change(change),
+        AdminAction::ExecuteSlash { .. } => {
+            Err(StakingError::InvalidArgument("Slashes are queued through approved proposals".to_string()))
+        }
+    }
+}
+
+async fn apply(action: AdminAction) -> StakingResult<()> {
+    match action {
+        AdminAction::AddAdmin(admin) => {
+            STATE.with(|s| s.borrow_mut().admins.insert(admin));
+            Ok(())
+        }
+        AdminAction::RemoveAdmin(admin) => {
+            STATE.with(|s| s.borrow_mut().admins.remove(&admin));
+            Ok(())
+        }
+        AdminAction::SetSlashConfig(config) => {
+            let config = slash_proposals::validate_slash_config(config)?;
+            STATE.with(|s| s.borrow_mut().slash_config = config);
+            Ok(())
+        }
+        AdminAction::SetTimelockDelay(delay) => {
+            STATE.with(|s| s.borrow_mut().timelock.delay = delay);
+            Ok(())
+        }
+        AdminAction::SetTreasury(treasury) => {
+            STATE.with(|s| s.borrow_mut().treasury = Some(treasury));
+            Ok(())
+        }
+        AdminAction::ChangeParameter(change) => governance::apply_change(change),
+        AdminAction::ExecuteSlash { proposal_id } => slash_proposals::execute_proposal(proposal_id).await,
+    }
+}
+
+fn set_status(action_id: u64, status: QueuedActionStatus) {
+    STATE.with(|s| {
+        if let Some(action) = s.borrow_mut().timelock.actions.get_mut(&action_id) {
+            action.status = status;
+        }
+    });
+}
+
+// Pending actions past their grace period are reported as expired
+fn with_effective_status(mut action: QueuedAction, now: u64) -> QueuedAction {
+    if matches!(action.status, QueuedActionStatus::Pending) && now > action.expires_at {
+        action.status = QueuedActionStatus::Expired;
+    }
+    action
+}
+
+// Queue an admin action. Changes to the admin set, the treasury and the timelock delay
+// itself can only be queued by a controller. Returns the action id.
+#[ic_cdk::update]
+#[candid_method(update)]
+fn queue_admin_action(action: AdminAction) -> StakingResult<u64> {
+    match action {
+        AdminAction::AddAdmin(_)
+        | AdminAction::RemoveAdmin(_)
+        | AdminAction::SetTreasury(_)
+        | AdminAction::SetTimelockDelay(_) => require_controller()?,
+        _ => require_admin()?,
+    }
+    validate(&action)?;
+
+    let caller = ic_cdk::caller();
+    let id = enqueue(action, caller);
+    ic_cdk::println!("Admin action {} queued by {}", id, caller);
+    Ok(id)
+}
+
+// Apply a queued action once its delay has passed. Anyone can trigger this, since
+// the action itself was already authorized when it was queued.
+#[ic_cdk::update]
+#[candid_method(update)]
+async fn execute_queued_action(action_id: u64) -> StakingResult<()> {
+    let now = time();
+    let mut expired_slash = None;
+    let action = STATE.with(|s| {
+        let timelock = &mut s.borrow_mut().timelock;
+        let queued = timelock.actions.get_mut(&action_id)
+            .ok_or(StakingError::ProposalNotFound)?;
+
+        if !matches!(queued.status, QueuedActionStatus::Pending) {
+            return Err(StakingError::ProposalNotOpen);
+        }
+        if now < queued.executable_at {
+            return Err(StakingError::TimelockNotExpired);
+        }
+        if now > queued.expires_at {
+            queued.status = QueuedActionStatus::Expired;
+            if let AdminAction::ExecuteSlash { proposal_id } = queued.action {
+                expired_slash = Some(proposal_id);
+            }
+            return Err(StakingError::ProposalExpired);
+        }
+
+        queued.status = QueuedActionStatus::Executing;
+        Ok(queued.action.clone())
+    });
+    // A slash that was never executed in time lapses with its action
+    if let Some(proposal_id) = expired_slash {
+        slash_proposals::set_status(proposal_id, SlashProposalStatus::Expired);
+    }
+    let action = action?;
+
+    match apply(action.clone()).await {
+        Ok(()) => {
+            set_status(action_id, QueuedActionStatus::Executed);
+            governance::settle_queued(action_id, ProposalStatus::Executed);
+            events::record(ic_cdk::caller(), EventKind::AdminActionExecuted { action_id, action });
+            ic_cdk::println!("Admin action {} executed", action_id);
+            Ok(())
+        }
+        Err(e) => {
+            set_status(action_id, QueuedActionStatus::Failed { reason: format!("{:?}", e) });
+            governance::settle_queued(action_id, ProposalStatus::Failed { reason: format!("{:?}", e) });
+            Err(e)
+        }
+    }
+}
+
+#[ic_cdk::update]
+#[candid_method(update)]
+fn cancel_queued_action(action_id: u64) -> StakingResult<()> {
+    require_admin()?;
+
+    let action = STATE.with(|s| {
+        let timelock = &mut s.borrow_mut().timelock;
+        let queued = timelock.actions.get_mut(&action_id)
+            .ok_or(StakingError::ProposalNotFound)?;
+
+        if !matches!(queued.status, QueuedActionStatus::Pending) {
+            return Err(StakingError::ProposalNotOpen);
+        }
+        queued.status = QueuedActionStatus::Cancelled;
+        Ok(queued.action.clone())
+    })?;
+
+    if let AdminAction::ExecuteSlash { proposal_id } = action {
+        slash_proposals::set_status(proposal_id, SlashProposalStatus::Cancelled);
+    }
+    governance::settle_queued(action_id, ProposalStatus::Cancelled);
+    events::record(ic_cdk::caller(), EventKind::AdminActionCancelled { action_id });
+    ic_cdk::println!("Admin action {} cancelled by {}", action_id, ic_cdk::caller());
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_queued_actions() -> Vec<QueuedAction> {
+    let now = time();
+    STATE.with(|s| {
+        s.borrow().timelock.actions.values()
+            .cloned()
+            .map(|a| with_effective_status(a, now))
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_timelock_delay() -> u64 {
+    STATE.with(|s| s.borrow().timelock.delay)
+}
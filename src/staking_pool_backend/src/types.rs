@@ -1,14 +1,24 @@
 
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
 use ic_ledger_types::Subaccount;
 use serde::Serialize;
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct Deposit {
+    pub id: u64,
     pub amount: u64,
     pub deposit_time: u64,
     pub lock_period: u64, // in seconds
     pub subaccount: Subaccount,
+    pub rewards: u64, // accrued and claimable, held in the reward subaccount
+    pub auto_compound: bool, // pay rewards into the position instead of accruing them
+}
+
+impl Deposit {
+    // Deposit time is in nanoseconds, the lock period in seconds
+    pub fn unlock_time(&self) -> u64 {
+        self.deposit_time.saturating_add(self.lock_period.saturating_mul(1_000_000_000))
+    }
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
@@ -16,7 +26,7 @@ pub struct UserDeposits {
     pub deposits: Vec<Deposit>,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub enum LockPeriod {
     Days90,
     Days180,
@@ -31,6 +41,20 @@ impl LockPeriod {
             LockPeriod::Days360 => 360 * 24 * 60 * 60,
         }
     }
+
+    pub fn from_seconds(seconds: u64) -> Option<LockPeriod> {
+        [LockPeriod::Days90, LockPeriod::Days180, LockPeriod::Days360]
+            .into_iter()
+            .find(|tier| tier.to_seconds() == seconds)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LockPeriod::Days90 => "Days90",
+            LockPeriod::Days180 => "Days180",
+            LockPeriod::Days360 => "Days360",
+        }
+    }
 }
 
 #[derive(CandidType, Deserialize, Debug)]
@@ -45,6 +69,38 @@ pub struct WithdrawArgs {
 }
 
 
+#[derive(CandidType, Deserialize, Debug)]
+pub struct TransferPositionArgs {
+    pub deposit_id: u64,
+    pub new_owner: Principal,
+    pub require_acceptance: bool, // recipient must call accept_position
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PositionTransfer {
+    pub deposit_id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub created_time: u64,
+}
+
+// Positions created by `split_position`. If a transfer failed part way, `failed` holds
+// the error and the remaining amounts were not split off.
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SplitResult {
+    pub new_deposit_ids: Vec<u64>,
+    pub failed: Option<StakingError>,
+}
+
+// Outcome of `merge_positions`. If a transfer failed part way, `failed` holds the error
+// and the positions missing from `merged_deposit_ids` were left as they were.
+#[derive(CandidType, Deserialize, Debug)]
+pub struct MergeResult {
+    pub deposit_id: u64, // the surviving position
+    pub merged_deposit_ids: Vec<u64>,
+    pub failed: Option<StakingError>,
+}
+
 #[derive(CandidType, Deserialize, Debug)]
 pub struct DepositIntention {
     pub subaccount: Subaccount,
@@ -62,6 +118,637 @@ pub enum StakingError {
     InvalidAmount,
     Unauthorized,
     DepositExpired, 
+    InvalidRecipient,
+    DepositBusy,
+    IncompatiblePositions,
+    Paused,
+    EmergencyModeInactive,
+    InvalidArgument(String),
+    ProposalNotFound,
+    ProposalNotOpen,
+    ProposalExpired,
+    TimelockNotExpired,
+    NoVotingPower,
+    VotingClosed,
+    DistributionInProgress,
+    ClaimInProgress,
+}
+
+pub type StakingResult<T> = Result<T, StakingError>;
+
+// M-of-N signer set that must approve every slash
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SlashConfig {
+    pub signers: Vec<Principal>,
+    pub threshold: u32,
+    pub proposal_ttl: u64, // in seconds
+}
+
+impl Default for SlashConfig {
+    fn default() -> Self {
+        SlashConfig {
+            signers: Vec::new(),
+            threshold: 1,
+            proposal_ttl: 3 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct ProposeSlashArgs {
+    pub amount: u64,
+    pub receiver: Principal,
+    pub justification: String,
+    pub target: Option<SlashTarget>, // every deposit if omitted
+}
+
+// The deposits a slash applies to
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum SlashTarget {
+    All,
+    Tier(LockPeriod),
+    DepositedBetween { from: u64, to: u64 }, // deposit time in nanoseconds, inclusive
+    Deposits(Vec<u64>),
+    Principals(Vec<Principal>),
+}
+
+impl SlashTarget {
+    pub fn includes(&self, owner: &Principal, deposit: &Deposit) -> bool {
+        match self {
+            SlashTarget::All => true,
+            SlashTarget::Tier(tier) => deposit.lock_period == tier.to_seconds(),
+            SlashTarget::DepositedBetween { from, to } => (*from..=*to).contains(&deposit.deposit_time),
+            SlashTarget::Deposits(ids) => ids.contains(&deposit.id),
+            SlashTarget::Principals(principals) => principals.contains(owner),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum SlashProposalStatus {
+    Open,
+    Queued { action_id: u64 }, // approved, waiting out the timelock
+    Executing,
+    Executed { report: SlashReport },
+    Failed { reason: String },
+    Expired,
+    Cancelled,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SlashProposal {
+    pub id: u64,
+    pub proposer: Principal,
+    pub amount: u64,
+    pub receiver: Principal,
+    pub justification: String,
+    pub target: SlashTarget,
+    pub approvals: Vec<Principal>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub status: SlashProposalStatus,
+}
+
+// Reward multipliers per lock tier, in basis points (10_000 = 1x)
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TierMultipliers {
+    pub days90: u32,
+    pub days180: u32,
+    pub days360: u32,
+}
+
+impl TierMultipliers {
+    pub fn for_lock_period(&self, lock_period: u64) -> u32 {
+        match LockPeriod::from_seconds(lock_period) {
+            Some(LockPeriod::Days90) => self.days90,
+            Some(LockPeriod::Days180) => self.days180,
+            Some(LockPeriod::Days360) => self.days360,
+            None => 10_000,
+        }
+    }
+}
+
+// Pool configuration that stakers can change through governance
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PoolParameters {
+    pub tier_multipliers: TierMultipliers,
+    pub reward_interval: u64, // seconds between automatic reward_pool runs, 0 = manual only
+    pub insurance_bps: u32, // share of each reward epoch routed to the insurance fund
+    pub protocol_fee_bps: u32, // share of each reward epoch sent to the treasury
+    pub reward_vesting_period: u64, // seconds over which accrued rewards vest
+}
+
+impl Default for PoolParameters {
+    fn default() -> Self {
+        PoolParameters {
+            tier_multipliers: TierMultipliers {
+                days90: 10_000,
+                days180: 10_000,
+                days360: 10_000,
+            },
+            reward_interval: 0,
+            insurance_bps: 0,
+            protocol_fee_bps: 0,
+            reward_vesting_period: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+// Every variant sets one parameter, so they share the prefix
+#[allow(clippy::enum_variant_names)]
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum ParameterChange {
+    SetTierMultipliers(TierMultipliers),
+    SetRewardInterval(u64),
+    SetInsuranceShare(u32), // in basis points
+    SetProtocolFee(u32),    // in basis points
+    SetRewardVesting(u64),  // in seconds
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SubmitProposalArgs {
+    pub title: String,
+    pub description: String,
+    pub change: ParameterChange,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum ProposalStatus {
+    Open,
+    Rejected,
+    Queued { action_id: u64 }, // passed, waiting out the timelock
+    Executed,
+    Failed { reason: String },
+    Cancelled,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: Principal,
+    pub title: String,
+    pub description: String,
+    pub change: ParameterChange,
+    pub created_at: u64,
+    pub voting_ends_at: u64,
+    pub yes_votes: u64,
+    pub no_votes: u64,
+    pub total_voting_power: u64, // at submission, used for the quorum
+    pub status: ProposalStatus,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct TierBalances {
+    pub days90: u64,
+    pub days180: u64,
+    pub days360: u64,
+    pub other: u64, // deposits whose lock period matches no tier
+}
+
+impl TierBalances {
+    pub fn add(&mut self, lock_period: u64, amount: u64) {
+        let slot = match LockPeriod::from_seconds(lock_period) {
+            Some(LockPeriod::Days90) => &mut self.days90,
+            Some(LockPeriod::Days180) => &mut self.days180,
+            Some(LockPeriod::Days360) => &mut self.days360,
+            None => &mut self.other,
+        };
+        *slot = slot.saturating_add(amount);
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SnapshotEntry {
+    pub owner: Principal,
+    pub staked: u64,
+    pub by_tier: TierBalances,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SnapshotInfo {
+    pub id: u64,
+    pub taken_at: u64,
+    pub total_staked: u64,
+    pub holders: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SnapshotPage {
+    pub info: SnapshotInfo,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+// Sensitive admin changes, applied only after the timelock delay
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum AdminAction {
+    AddAdmin(Principal),
+    RemoveAdmin(Principal),
+    SetSlashConfig(SlashConfig),
+    SetTimelockDelay(u64), // in seconds
+    SetTreasury(Principal), // receives the protocol fee
+    ChangeParameter(ParameterChange),
+    ExecuteSlash { proposal_id: u64 }, // queued automatically once a slash proposal is approved
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum QueuedActionStatus {
+    Pending,
+    Executing,
+    Executed,
+    Failed { reason: String },
+    Cancelled,
+    Expired,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct QueuedAction {
+    pub id: u64,
+    pub action: AdminAction,
+    pub queued_by: Principal,
+    pub queued_at: u64,
+    pub executable_at: u64,
+    pub expires_at: u64,
+    pub status: QueuedActionStatus,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseScope {
+    Deposits,
+    Withdrawals,
+    Rewards,
+    Slashing,
+    All, // read-only maintenance mode, blocks every update
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct PauseStatus {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub rewards: bool,
+    pub slashing: bool,
+    pub all: bool,
+}
+
+impl PauseStatus {
+    pub fn is_paused(&self, scope: PauseScope) -> bool {
+        self.all || match scope {
+            PauseScope::Deposits => self.deposits,
+            PauseScope::Withdrawals => self.withdrawals,
+            PauseScope::Rewards => self.rewards,
+            PauseScope::Slashing => self.slashing,
+            PauseScope::All => false,
+        }
+    }
+
+    pub fn set(&mut self, scope: PauseScope, paused: bool) {
+        match scope {
+            PauseScope::Deposits => self.deposits = paused,
+            PauseScope::Withdrawals => self.withdrawals = paused,
+            PauseScope::Rewards => self.rewards = paused,
+            PauseScope::Slashing => self.slashing = paused,
+            // Unpausing everything also clears the individual scopes
+            PauseScope::All if paused => self.all = true,
+            PauseScope::All => *self = PauseStatus::default(),
+        }
+    }
 }
 
-pub type StakingResult<T> = Result<T, StakingError>;
\ No newline at end of file
+// ICRC-7 types. Every deposit is a token whose id is the deposit id.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type TransferResult = Result<Nat, TransferError>;
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SupportedStandard {
+    pub name: String,
+    pub url: String,
+}
+
+// ICRC-3 block log
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+candid::define_function!(pub GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksCallback,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct DataCertificate {
+    #[serde(with = "serde_bytes")]
+    pub certificate: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub hash_tree: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct GetArchivesArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct BlockType {
+    pub block_type: String,
+    pub url: String,
+}
+// Entry in the append-only event log. `principal` is the user the event concerns,
+// or the caller for admin events. Amounts are in e8s.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Event {
+    pub id: u64,
+    pub timestamp: u64,
+    pub principal: Principal,
+    pub kind: EventKind,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum EventKind {
+    DepositIntentionCreated { subaccount: Subaccount, expected_amount: u64, lock_period: u64 },
+    DepositIntentionExpired { subaccount: Subaccount },
+    DepositConfirmed { deposit_id: u64, amount: u64, lock_period: u64 },
+    Withdrawal { deposit_id: u64, amount: u64, block_index: u64 },
+    EmergencyWithdrawal { deposit_id: u64, amount: u64, block_index: u64 },
+    PositionSplit { deposit_id: u64, new_deposit_id: u64, amount: u64, block_index: u64 },
+    PositionMerged { deposit_id: u64, into_deposit_id: u64, amount: u64, block_index: u64 },
+    PositionTransferred { deposit_id: u64, to: Principal },
+    RewardPaid { deposit_id: u64, amount: u64, block_index: u64 },
+    RewardAccrued { deposit_id: u64, amount: u64 },
+    RewardsClaimed { deposit_ids: Vec<u64>, amount: u64, block_index: u64 },
+    RewardsForfeited { deposit_id: u64, amount: u64 },
+    Slashed { deposit_id: u64, amount: u64, receiver: Principal, block_index: u64 },
+    HeldSlashDelivered { slash_id: u64, amount: u64, receiver: Principal, block_index: u64 },
+    AdminActionQueued { action_id: u64, action: AdminAction },
+    AdminActionExecuted { action_id: u64, action: AdminAction },
+    AdminActionCancelled { action_id: u64 },
+    ParameterChanged { change: ParameterChange },
+    ProposalSubmitted { proposal_id: u64, change: ParameterChange },
+    VoteCast { proposal_id: u64, approve: bool, power: u64 },
+    ProposalFinalized { proposal_id: u64, status: ProposalStatus },
+    SlashProposed { proposal_id: u64, amount: u64, receiver: Principal },
+    SlashApproved { proposal_id: u64 },
+    AutoCompoundSet { deposit_id: u64, enabled: bool },
+    PositionOffered { deposit_id: u64, to: Principal },
+    PositionOfferCancelled { deposit_id: u64 },
+    SnapshotTaken { snapshot_id: u64 },
+    ArchiveWasmSet { size: u64 },
+    Paused { scope: PauseScope },
+    Unpaused { scope: PauseScope },
+    EmergencyModeSet { enabled: bool },
+    DepositCorrected { deposit_id: u64, recorded: u64, ledger: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct CertifiedTotalStaked {
+    pub total_staked: u64,
+    #[serde(with = "serde_bytes")]
+    pub certificate: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub witness: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct CertifiedDeposits {
+    pub deposits: Vec<Deposit>,
+    #[serde(with = "serde_bytes")]
+    pub certificate: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub witness: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BalanceMismatch {
+    pub deposit_id: u64,
+    pub owner: Principal,
+    pub subaccount: Subaccount,
+    pub recorded: u64,
+    pub ledger: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OrphanedSubaccount {
+    pub subaccount: Subaccount,
+    pub balance: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReconciliationReport {
+    pub total_staked: u64,
+    pub sum_of_deposits: u64,
+    pub deposits_checked: u64,
+    pub mismatches: Vec<BalanceMismatch>,
+    pub reward_balance: Option<u64>,
+    pub reward_reserved: u64, // accrued, unclaimed and carried rewards the reward subaccount owes
+    pub reward_shortfall: u64, // by how much `reward_balance` falls short of `reward_reserved`
+    pub slash_balance: Option<u64>,
+    pub slash_held: u64, // collected by slashes but not yet delivered
+    pub slash_shortfall: u64, // by how much `slash_balance` falls short of `slash_held`
+    pub orphaned: Vec<OrphanedSubaccount>,
+    pub failed_checks: Vec<Subaccount>, // balances the ledger could not report
+    pub corrected: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum RewardOutcome {
+    Paid { block_index: u64 }, // compounded into the position
+    Accrued,                   // added to the position's claimable rewards
+    Skipped,        // share plus carried dust did not cover the fee
+    Failed(String), // transfer failed; the amount is carried forward
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RewardAllocation {
+    pub deposit_id: u64,
+    pub owner: Principal,
+    pub share: u64,      // this epoch's share, before the fee
+    pub carried_in: u64, // dust carried from earlier epochs
+    pub outcome: RewardOutcome,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct RewardReport {
+    pub reward_balance: u64,
+    pub protocol_fee: u64, // sent to the treasury, after its transfer fee
+    pub accrued: u64,      // added to claimable rewards
+    pub to_insurance: u64, // routed to the insurance fund, after its transfer fee
+    pub distributed: u64,     // compounded into deposits, after fees
+    pub fees: u64,
+    pub carried_forward: u64, // left in the reward subaccount for skipped deposits
+    pub allocations: Vec<RewardAllocation>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum SlashOutcome {
+    Collected { block_index: u64 },
+    Exempt, // deduction would not have covered the transfer fee
+    Failed(String),
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SlashDeduction {
+    pub deposit_id: u64,
+    pub owner: Principal,
+    pub deducted: u64, // taken from the deposit, fee included
+    pub fee: u64,
+    pub outcome: SlashOutcome,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SlashReport {
+    pub slash_id: u64, // see `get_slash_history`
+    pub amount: u64, // requested for the receiver
+    pub receiver: Principal,
+    pub from_insurance: u64, // paid to the receiver by the insurance fund
+    pub insurance_block_index: Option<u64>,
+    pub total_deducted: u64,
+    pub fees: u64, // every ledger fee paid, including the transfers to the receiver
+    pub sent_to_receiver: u64, // collected from deposits
+    pub receiver_block_index: Option<u64>,
+    pub held: u64, // collected but not delivered; left in the slash subaccount
+    pub deductions: Vec<SlashDeduction>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SlashRecord {
+    pub id: u64,
+    pub proposal_id: u64,
+    pub reason: String,
+    pub target: SlashTarget,
+    pub from_insurance: u64,
+    pub amount: u64, // deducted from deposits, fees included
+    pub receiver: Principal,
+    pub held: u64, // collected but not yet delivered, see `deliver_held_slash`
+    pub timestamp: u64,
+    pub deductions: Vec<SlashDeduction>, // deposits that lost funds
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DepositSlash {
+    pub slash_id: u64,
+    pub timestamp: u64,
+    pub reason: String,
+    pub deducted: u64,
+    pub fee: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InsuranceFund {
+    pub address: String, // send ICP here to top up the fund
+    pub balance: Option<u64>,
+    pub balance_updated_at: Option<u64>,
+    pub contributed: u64, // routed from rewards
+    pub paid_out: u64,    // used to cover slashes
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProtocolFee {
+    pub fee_bps: u32,
+    pub max_fee_bps: u32,
+    pub treasury: Option<Principal>, // no fee is taken until a treasury is set
+    pub collected: u64, // sent to the treasury so far, in e8s
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RewardClaim {
+    pub deposit_ids: Vec<u64>, // positions whose rewards were claimed
+    pub amount: u64,           // received, after the fee
+    pub fee: u64,
+    pub block_index: u64,
+}
+
+// Rewards accrued by a position in one epoch, vesting linearly from `start` to `end`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VestingGrant {
+    pub id: u64,
+    pub deposit_id: u64,
+    pub amount: u64,
+    pub claimed: u64,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VestedRewards {
+    pub vested: u64,   // claimable now
+    pub unvested: u64, // forfeited if the position is withdrawn
+    pub vesting_period: u64, // for new grants, in seconds
+    pub grants: Vec<VestingGrant>,
+}
@@ -0,0 +1,156 @@
+// Reward vesting. Rewards a position accrues in an epoch are recorded as a grant that
+// vests linearly over `reward_vesting_period`, and only the vested part can be claimed.
+// A position's `rewards` is what its grants still hold, vested or not. Withdrawing the
+// position forfeits what hasn't vested yet; forfeited rewards are no longer reserved in
+// the reward subaccount, so they go to the next epoch.
+use candid::{candid_method, Principal};
+use ic_cdk::api::time;
+use std::collections::HashMap;
+
+use crate::types::*;
+use crate::{certification, State, STATE};
+
+fn vested(grant: &VestingGrant, now: u64) -> u64 {
+    if now >= grant.end || grant.end <= grant.start {
+        return grant.amount;
+    }
+    let elapsed = now.saturating_sub(grant.start);
+    (grant.amount as u128 * elapsed as u128 / (grant.end - grant.start) as u128) as u64
+}
+
+fn claimable(grant: &VestingGrant, now: u64) -> u64 {
+    vested(grant, now).saturating_sub(grant.claimed)
+}
+
+// Record rewards accrued by a position. Grants that have fully vested are folded into
+// one, so a position that is never claimed keeps a bounded number of grants. Grants
+// with a claim in flight are left alone, so `restore` can still find them.
+pub(crate) fn grant(state: &mut State, deposit_id: u64, amount: u64) {
+    let now = time();
+    let id = state.next_vesting_id;
+    state.next_vesting_id += 1;
+    let end = now.saturating_add(state.parameters.reward_vesting_period.saturating_mul(1_000_000_000));
+
+    let grants = state.vesting.entry(deposit_id).or_default();
+    let claiming = &state.claiming_grants;
+    let (done, mut pending): (Vec<_>, Vec<_>) = grants.drain(..)
+        .partition(|g| now >= g.end && !claiming.contains(&g.id));
+    if let Some(first) = done.first() {
+        let end = done.iter().map(|g| g.end).max().unwrap_or(first.end);
+        pending.insert(0, VestingGrant {
+            id: first.id,
+            deposit_id,
+            amount: done.iter().map(|g| g.amount - g.claimed).sum(),
+            claimed: 0,
+            start: end,
+            end,
+        });
+    }
+    pending.push(VestingGrant { id, deposit_id, amount, claimed: 0, start: now, end });
+    *grants = pending;
+}
+
+// Mark what has vested on a position's grants as claimed. Returns the amount taken per
+// grant, for `restore` in case the payout fails. The grants count as being claimed
+// until `finish_claim` or `restore`.
+pub(crate) fn take_vested(state: &mut State, deposit_id: u64, now: u64) -> Vec<(u64, u64)> {
+    let mut taken = Vec::new();
+    for grant in state.vesting.get_mut(&deposit_id).into_iter().flatten() {
+        let amount = claimable(grant, now);
+        if amount > 0 {
+            grant.claimed += amount;
+            taken.push((grant.id, amount));
+        }
+    }
+    state.claiming_grants.extend(taken.iter().map(|(id, _)| *id));
+    taken
+}
+
+// The claim of `taken` has been paid
+pub(crate) fn finish_claim(state: &mut State, taken: &[(u64, u64)]) {
+    for (id, _) in taken {
+        state.claiming_grants.remove(id);
+    }
+}
+
+// Undo `take_vested` on whichever position now holds the grants, and re-certify its
+// owner. Returns the amount of grants that are gone because their position has closed
+// in the meantime.
+pub(crate) fn restore(state: &mut State, taken: &[(u64, u64)]) -> u64 {
+    finish_claim(state, taken);
+    let grants: HashMap<u64, u64> = taken.iter().copied().collect();
+    let mut restored = HashMap::new();
+    for (deposit_id, deposit_grants) in state.vesting.iter_mut() {
+        for grant in deposit_grants.iter_mut() {
+            if let Some(amount) = grants.get(&grant.id) {
+                grant.claimed = grant.claimed.saturating_sub(*amount);
+                *restored.entry(*deposit_id).or_insert(0) += amount;
+            }
+        }
+    }
+    for (deposit_id, amount) in &restored {
+        let Some((owner, _)) = state.find_deposit(*deposit_id) else {
+            continue;
+        };
+        if let Some(deposit) = state.get_deposit_mut(&owner, *deposit_id) {
+            deposit.rewards = deposit.rewards.saturating_add(*amount);
+        }
+        certification::certify_user(state, &owner);
+    }
+    let total: u64 = taken.iter().map(|(_, amount)| amount).sum();
+    total.saturating_sub(restored.values().sum())
+}
+
+// Drop grants that have been claimed in full
+pub(crate) fn prune(state: &mut State, deposit_ids: &[u64]) {
+    for deposit_id in deposit_ids {
+        if let Some(grants) = state.vesting.get_mut(deposit_id) {
+            grants.retain(|g| g.claimed < g.amount);
+            if grants.is_empty() {
+                state.vesting.remove(deposit_id);
+            }
+        }
+    }
+}
+
+// Close a position's grants. Returns what had vested but was not yet claimed, and what
+// is forfeited.
+pub(crate) fn release(state: &mut State, deposit_id: u64) -> (u64, u64) {
+    let now = time();
+    let grants = state.vesting.remove(&deposit_id).unwrap_or_default();
+    let unclaimed = grants.iter().map(|g| claimable(g, now)).sum();
+    let forfeited = grants.iter().map(|g| g.amount - vested(g, now).max(g.claimed)).sum();
+    (unclaimed, forfeited)
+}
+
+// Move a merged position's grants to the position it was merged into. They keep vesting
+// on their own schedule.
+pub(crate) fn transfer(state: &mut State, from: u64, to: u64) {
+    let Some(mut grants) = state.vesting.remove(&from) else {
+        return;
+    };
+    for grant in grants.iter_mut() {
+        grant.deposit_id = to;
+    }
+    state.vesting.entry(to).or_default().extend(grants);
+}
+
+// Vested rewards include those left by positions that have been withdrawn
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_vested_rewards(user: Principal) -> VestedRewards {
+    let now = time();
+    STATE.with(|s| {
+        let state = s.borrow();
+        let grants: Vec<VestingGrant> = state.get_user_deposits(&user)
+            .map(|ud| ud.deposits.iter().flat_map(|d| state.vesting.get(&d.id).into_iter().flatten()).cloned().collect())
+            .unwrap_or_default();
+        VestedRewards {
+            vested: grants.iter().map(|g| claimable(g, now)).sum::<u64>()
+                + state.unclaimed_rewards.get(&user).copied().unwrap_or(0),
+            unvested: grants.iter().map(|g| g.amount - vested(g, now).max(g.claimed)).sum(),
+            vesting_period: state.parameters.reward_vesting_period,
+            grants,
+        }
+    })
+}
@@ -1,15 +1,23 @@
-use candid::{decode_one, encode_args, Principal};
-use pocket_ic::PocketIc;
+use candid::{decode_one, encode_args, encode_one, Principal};
+use ic_ledger_types::{AccountIdentifier, Subaccount, Tokens, DEFAULT_SUBACCOUNT, MAINNET_LEDGER_CANISTER_ID};
+use pocket_ic::{PocketIc, PocketIcBuilder};
 use std::time::Duration;
 
 const WASM_PATH: &str = "../../target/wasm32-unknown-unknown/release/staking_pool_backend.wasm";
+const ARCHIVE_WASM_PATH: &str = "../../target/wasm32-unknown-unknown/release/staking_pool_archive.wasm";
+// ICP ledger release build, e.g. ledger-canister.wasm.gz from https://github.com/dfinity/ic/releases
+const LEDGER_WASM_PATH: &str = "../../target/ledger/ledger-canister.wasm.gz";
+const LEDGER_FEE: u64 = 10_000;
 
 #[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
 struct Deposit {
+    id: u64,
     amount: u64,
     deposit_time: u64,
     lock_period: u64,
     subaccount: [u8; 32],
+    rewards: u64,
+    auto_compound: bool,
 }
 
 #[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
@@ -30,6 +38,255 @@ struct WithdrawArgs {
     deposit_index: usize,
 }
 
+#[derive(candid::CandidType)]
+struct TransferPositionArgs {
+    deposit_id: u64,
+    new_owner: Principal,
+    require_acceptance: bool,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SplitResult {
+    new_deposit_ids: Vec<u64>,
+    failed: Option<StakingError>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct MergeResult {
+    deposit_id: u64,
+    merged_deposit_ids: Vec<u64>,
+    failed: Option<StakingError>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+struct PositionTransfer {
+    deposit_id: u64,
+    from: Principal,
+    to: Principal,
+    created_time: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug, PartialEq)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<[u8; 32]>,
+}
+
+#[derive(candid::CandidType)]
+struct TransferArg {
+    from_subaccount: Option<[u8; 32]>,
+    to: Account,
+    token_id: candid::Nat,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: candid::Nat },
+    GenericError { error_code: candid::Nat, message: String },
+    GenericBatchError { error_code: candid::Nat, message: String },
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SupportedStandard {
+    name: String,
+    url: String,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(candid::Nat),
+    Int(candid::Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+struct GetBlocksArgs {
+    start: candid::Nat,
+    length: candid::Nat,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+struct BlockWithId {
+    id: candid::Nat,
+    block: Value,
+}
+
+candid::define_function!(GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+struct ArchivedBlocks {
+    args: Vec<GetBlocksArgs>,
+    callback: GetBlocksCallback,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Debug)]
+struct GetBlocksResult {
+    log_length: candid::Nat,
+    blocks: Vec<BlockWithId>,
+    archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct DataCertificate {
+    certificate: Vec<u8>,
+    hash_tree: Vec<u8>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct GetArchivesArgs {
+    from: Option<Principal>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct ArchiveInfo {
+    canister_id: Principal,
+    start: candid::Nat,
+    end: candid::Nat,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct CertifiedTotalStaked {
+    total_staked: u64,
+    certificate: Vec<u8>,
+    witness: Vec<u8>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct CertifiedDeposits {
+    deposits: Vec<Deposit>,
+    certificate: Vec<u8>,
+    witness: Vec<u8>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct BalanceMismatch {
+    deposit_id: u64,
+    owner: Principal,
+    subaccount: [u8; 32],
+    recorded: u64,
+    ledger: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct OrphanedSubaccount {
+    subaccount: [u8; 32],
+    balance: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct ReconciliationReport {
+    total_staked: u64,
+    sum_of_deposits: u64,
+    deposits_checked: u64,
+    mismatches: Vec<BalanceMismatch>,
+    reward_balance: Option<u64>,
+    reward_reserved: u64,
+    reward_shortfall: u64,
+    slash_balance: Option<u64>,
+    slash_held: u64,
+    slash_shortfall: u64,
+    orphaned: Vec<OrphanedSubaccount>,
+    failed_checks: Vec<[u8; 32]>,
+    corrected: bool,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum RewardOutcome {
+    Paid { block_index: u64 },
+    Accrued,
+    Skipped,
+    Failed(String),
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct RewardAllocation {
+    deposit_id: u64,
+    owner: Principal,
+    share: u64,
+    carried_in: u64,
+    outcome: RewardOutcome,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct RewardReport {
+    reward_balance: u64,
+    protocol_fee: u64,
+    accrued: u64,
+    to_insurance: u64,
+    distributed: u64,
+    fees: u64,
+    carried_forward: u64,
+    allocations: Vec<RewardAllocation>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct RewardClaim {
+    deposit_ids: Vec<u64>,
+    amount: u64,
+    fee: u64,
+    block_index: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct VestingGrant {
+    id: u64,
+    deposit_id: u64,
+    amount: u64,
+    claimed: u64,
+    start: u64,
+    end: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct VestedRewards {
+    vested: u64,
+    unvested: u64,
+    vesting_period: u64,
+    grants: Vec<VestingGrant>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct ProtocolFee {
+    fee_bps: u32,
+    max_fee_bps: u32,
+    treasury: Option<Principal>,
+    collected: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct InsuranceFund {
+    address: String,
+    balance: Option<u64>,
+    balance_updated_at: Option<u64>,
+    contributed: u64,
+    paid_out: u64,
+}
+
 #[derive(candid::CandidType, candid::Deserialize, Debug)]
 struct DepositIntention {
     subaccount: [u8; 32],
@@ -55,6 +312,438 @@ enum StakingError {
     InvalidAmount,
     Unauthorized,
     DepositExpired,
+    InvalidRecipient,
+    DepositBusy,
+    IncompatiblePositions,
+    Paused,
+    EmergencyModeInactive,
+    InvalidArgument(String),
+    ProposalNotFound,
+    ProposalNotOpen,
+    ProposalExpired,
+    TimelockNotExpired,
+    NoVotingPower,
+    VotingClosed,
+    DistributionInProgress,
+    ClaimInProgress,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SlashConfig {
+    signers: Vec<Principal>,
+    threshold: u32,
+    proposal_ttl: u64,
+}
+
+#[derive(candid::CandidType)]
+struct ProposeSlashArgs {
+    amount: u64,
+    receiver: Principal,
+    justification: String,
+    target: Option<SlashTarget>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum SlashTarget {
+    All,
+    Tier(LockPeriod),
+    DepositedBetween { from: u64, to: u64 },
+    Deposits(Vec<u64>),
+    Principals(Vec<Principal>),
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum SlashOutcome {
+    Collected { block_index: u64 },
+    Exempt,
+    Failed(String),
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SlashDeduction {
+    deposit_id: u64,
+    owner: Principal,
+    deducted: u64,
+    fee: u64,
+    outcome: SlashOutcome,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SlashReport {
+    slash_id: u64,
+    amount: u64,
+    receiver: Principal,
+    from_insurance: u64,
+    insurance_block_index: Option<u64>,
+    total_deducted: u64,
+    fees: u64,
+    sent_to_receiver: u64,
+    receiver_block_index: Option<u64>,
+    held: u64,
+    deductions: Vec<SlashDeduction>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SlashRecord {
+    id: u64,
+    proposal_id: u64,
+    reason: String,
+    target: SlashTarget,
+    from_insurance: u64,
+    amount: u64,
+    receiver: Principal,
+    held: u64,
+    timestamp: u64,
+    deductions: Vec<SlashDeduction>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct DepositSlash {
+    slash_id: u64,
+    timestamp: u64,
+    reason: String,
+    deducted: u64,
+    fee: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum SlashProposalStatus {
+    Open,
+    Queued { action_id: u64 },
+    Executing,
+    Executed { report: SlashReport },
+    Failed { reason: String },
+    Expired,
+    Cancelled,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SlashProposal {
+    id: u64,
+    proposer: Principal,
+    amount: u64,
+    receiver: Principal,
+    justification: String,
+    target: SlashTarget,
+    approvals: Vec<Principal>,
+    created_at: u64,
+    expires_at: u64,
+    status: SlashProposalStatus,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug, PartialEq)]
+struct TierBalances {
+    days90: u64,
+    days180: u64,
+    days360: u64,
+    other: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SnapshotEntry {
+    owner: Principal,
+    staked: u64,
+    by_tier: TierBalances,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SnapshotInfo {
+    id: u64,
+    taken_at: u64,
+    total_staked: u64,
+    holders: u64,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct SnapshotPage {
+    info: SnapshotInfo,
+    entries: Vec<SnapshotEntry>,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug, PartialEq)]
+struct TierMultipliers {
+    days90: u32,
+    days180: u32,
+    days360: u32,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct PoolParameters {
+    tier_multipliers: TierMultipliers,
+    reward_interval: u64,
+    insurance_bps: u32,
+    protocol_fee_bps: u32,
+    reward_vesting_period: u64,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum ParameterChange {
+    SetTierMultipliers(TierMultipliers),
+    SetRewardInterval(u64),
+    SetInsuranceShare(u32),
+    SetProtocolFee(u32),
+    SetRewardVesting(u64),
+}
+
+#[derive(candid::CandidType)]
+struct SubmitProposalArgs {
+    title: String,
+    description: String,
+    change: ParameterChange,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug, PartialEq)]
+enum ProposalStatus {
+    Open,
+    Rejected,
+    Queued { action_id: u64 },
+    Executed,
+    Failed { reason: String },
+    Cancelled,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum AdminAction {
+    AddAdmin(Principal),
+    RemoveAdmin(Principal),
+    SetSlashConfig(SlashConfig),
+    SetTimelockDelay(u64),
+    SetTreasury(Principal),
+    ChangeParameter(ParameterChange),
+    ExecuteSlash { proposal_id: u64 },
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum QueuedActionStatus {
+    Pending,
+    Executing,
+    Executed,
+    Failed { reason: String },
+    Cancelled,
+    Expired,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct QueuedAction {
+    id: u64,
+    action: AdminAction,
+    queued_by: Principal,
+    queued_at: u64,
+    executable_at: u64,
+    expires_at: u64,
+    status: QueuedActionStatus,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+struct Event {
+    id: u64,
+    timestamp: u64,
+    principal: Principal,
+    kind: EventKind,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug)]
+enum EventKind {
+    DepositIntentionCreated { subaccount: [u8; 32], expected_amount: u64, lock_period: u64 },
+    DepositIntentionExpired { subaccount: [u8; 32] },
+    DepositConfirmed { deposit_id: u64, amount: u64, lock_period: u64 },
+    Withdrawal { deposit_id: u64, amount: u64, block_index: u64 },
+    EmergencyWithdrawal { deposit_id: u64, amount: u64, block_index: u64 },
+    PositionSplit { deposit_id: u64, new_deposit_id: u64, amount: u64, block_index: u64 },
+    PositionMerged { deposit_id: u64, into_deposit_id: u64, amount: u64, block_index: u64 },
+    PositionTransferred { deposit_id: u64, to: Principal },
+    RewardPaid { deposit_id: u64, amount: u64, block_index: u64 },
+    RewardAccrued { deposit_id: u64, amount: u64 },
+    RewardsClaimed { deposit_ids: Vec<u64>, amount: u64, block_index: u64 },
+    RewardsForfeited { deposit_id: u64, amount: u64 },
+    Slashed { deposit_id: u64, amount: u64, receiver: Principal, block_index: u64 },
+    HeldSlashDelivered { slash_id: u64, amount: u64, receiver: Principal, block_index: u64 },
+    AdminActionQueued { action_id: u64, action: AdminAction },
+    AdminActionExecuted { action_id: u64, action: AdminAction },
+    AdminActionCancelled { action_id: u64 },
+    ParameterChanged { change: ParameterChange },
+    ProposalSubmitted { proposal_id: u64, change: ParameterChange },
+    VoteCast { proposal_id: u64, approve: bool, power: u64 },
+    ProposalFinalized { proposal_id: u64, status: ProposalStatus },
+    SlashProposed { proposal_id: u64, amount: u64, receiver: Principal },
+    SlashApproved { proposal_id: u64 },
+    AutoCompoundSet { deposit_id: u64, enabled: bool },
+    PositionOffered { deposit_id: u64, to: Principal },
+    PositionOfferCancelled { deposit_id: u64 },
+    SnapshotTaken { snapshot_id: u64 },
+    ArchiveWasmSet { size: u64 },
+    Paused { scope: PauseScope },
+    Unpaused { scope: PauseScope },
+    EmergencyModeSet { enabled: bool },
+    DepositCorrected { deposit_id: u64, recorded: u64, ledger: u64 },
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Clone, Copy, Debug)]
+enum PauseScope {
+    Deposits,
+    Withdrawals,
+    Rewards,
+    Slashing,
+    All,
+}
+
+#[derive(candid::CandidType, candid::Deserialize, Debug, Default, PartialEq)]
+struct PauseStatus {
+    deposits: bool,
+    withdrawals: bool,
+    rewards: bool,
+    slashing: bool,
+    all: bool,
+}
+
+// Queue an admin action as the controller, wait out the timelock and execute it
+fn apply_admin_action(pic: &PocketIc, canister_id: Principal, action: AdminAction) -> Result<(), StakingError> {
+    let controller = Principal::anonymous();
+    let result = pic.update_call(canister_id, controller, "queue_admin_action", encode_args((action,)).unwrap())
+        .expect("Failed to call queue_admin_action");
+    let action_id = decode_one::<Result<u64, StakingError>>(&result).unwrap()?;
+
+    pic.advance_time(Duration::from_secs(2 * 24 * 60 * 60 + 1));
+    let result = pic.update_call(canister_id, controller, "execute_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to call execute_queued_action");
+    decode_one(&result).unwrap()
+}
+
+// Make `signer` the only slash signer, so a single proposal is approved immediately
+fn configure_slash_signer(pic: &PocketIc, canister_id: Principal, signer: Principal) {
+    let config = SlashConfig {
+        signers: vec![signer],
+        threshold: 1,
+        proposal_ttl: 24 * 60 * 60,
+    };
+    let response = apply_admin_action(pic, canister_id, AdminAction::SetSlashConfig(config));
+    assert!(response.is_ok(), "Failed to configure slash signer: {:?}", response);
+}
+
+// Propose a slash as `signer` (see `configure_slash_signer`), wait out the timelock
+// and execute it
+fn execute_slash(pic: &PocketIc, canister_id: Principal, signer: Principal, args: ProposeSlashArgs) -> Result<(), StakingError> {
+    let result = pic.update_call(canister_id, signer, "propose_slash", encode_args((args,)).unwrap())
+        .expect("Failed to call propose_slash");
+    let proposal_id = decode_one::<Result<u64, StakingError>>(&result).unwrap()?;
+
+    let result = pic.query_call(canister_id, signer, "get_queued_actions", encode_args(()).unwrap())
+        .expect("Failed to query queued actions");
+    let actions: Vec<QueuedAction> = decode_one(&result).unwrap();
+    let action_id = actions.iter()
+        .find(|a| matches!(a.action, AdminAction::ExecuteSlash { proposal_id: id } if id == proposal_id))
+        .map(|a| a.id)
+        .expect("An approved slash should be queued");
+
+    pic.advance_time(Duration::from_secs(2 * 24 * 60 * 60 + 1));
+    let result = pic.update_call(canister_id, signer, "execute_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to call execute_queued_action");
+    decode_one(&result).unwrap()
+}
+
+// ICP ledger init arguments; the fields these tests leave out are optional
+#[derive(candid::CandidType)]
+struct LedgerInitArgs {
+    minting_account: String,
+    initial_values: Vec<(String, Tokens)>,
+    send_whitelist: Vec<Principal>,
+    transfer_fee: Option<Tokens>,
+    token_symbol: Option<String>,
+    token_name: Option<String>,
+}
+
+#[derive(candid::CandidType)]
+enum LedgerCanisterPayload {
+    Init(LedgerInitArgs),
+}
+
+// Like `setup`, with an ICP ledger at the mainnet ledger id that gives every one of
+// `users` 100 ICP
+fn setup_with_ledger(users: &[Principal]) -> (PocketIc, Principal) {
+    let pic = PocketIcBuilder::new().with_nns_subnet().with_application_subnet().build();
+
+    let ledger_id = pic.create_canister_with_id(None, None, MAINNET_LEDGER_CANISTER_ID)
+        .expect("Failed to create the ledger canister");
+    let minting_account = AccountIdentifier::new(&Principal::from_slice(&[1]), &DEFAULT_SUBACCOUNT);
+    let init = LedgerCanisterPayload::Init(LedgerInitArgs {
+        minting_account: minting_account.to_string(),
+        initial_values: users.iter()
+            .map(|user| (AccountIdentifier::new(user, &DEFAULT_SUBACCOUNT).to_string(), Tokens::from_e8s(100 * 100_000_000)))
+            .collect(),
+        send_whitelist: Vec::new(),
+        transfer_fee: Some(Tokens::from_e8s(LEDGER_FEE)),
+        token_symbol: Some("ICP".to_string()),
+        token_name: Some("Internet Computer".to_string()),
+    });
+    let ledger_wasm = std::fs::read(LEDGER_WASM_PATH).expect("Failed to read the ICP ledger wasm. Download ledger-canister.wasm.gz to target/ledger first");
+    pic.install_canister(ledger_id, ledger_wasm, encode_one(init).unwrap(), None);
+
+    let canister_id = pic.create_canister();
+    pic.add_cycles(canister_id, 2_000_000_000_000);
+    let wasm = std::fs::read(WASM_PATH).expect("Failed to read wasm. Run 'cargo build --target wasm32-unknown-unknown --release' first");
+    pic.install_canister(canister_id, wasm, vec![], None);
+
+    (pic, canister_id)
+}
+
+// Send `amount` from `from`'s default account to `to` on the ledger
+fn ledger_transfer(pic: &PocketIc, from: Principal, to: AccountIdentifier, amount: u64) {
+    let args = ic_ledger_types::TransferArgs {
+        memo: ic_ledger_types::Memo(0),
+        amount: Tokens::from_e8s(amount),
+        fee: Tokens::from_e8s(LEDGER_FEE),
+        from_subaccount: None,
+        to,
+        created_at_time: None,
+    };
+    let result = pic.update_call(MAINNET_LEDGER_CANISTER_ID, from, "transfer", encode_one(args).unwrap())
+        .expect("Failed to call the ledger");
+    let response: ic_ledger_types::TransferResult = decode_one(&result).unwrap();
+    response.expect("Ledger transfer failed");
+}
+
+fn ledger_balance(pic: &PocketIc, account: AccountIdentifier) -> u64 {
+    let args = ic_ledger_types::AccountBalanceArgs { account };
+    let result = pic.query_call(MAINNET_LEDGER_CANISTER_ID, Principal::anonymous(), "account_balance", encode_one(args).unwrap())
+        .expect("Failed to query the ledger");
+    decode_one::<Tokens>(&result).unwrap().e8s()
+}
+
+fn get_deposits(pic: &PocketIc, canister_id: Principal, user: Principal) -> Vec<Deposit> {
+    let result = pic.query_call(canister_id, user, "get_deposits", encode_args((user,)).unwrap())
+        .expect("Failed to query deposits");
+    decode_one(&result).unwrap()
+}
+
+// Stake `amount` for `user` through the ledger and return the confirmed deposit
+fn stake(pic: &PocketIc, canister_id: Principal, user: Principal, amount: u64, lock_period: LockPeriod) -> Deposit {
+    let args = DepositArgs { amount, lock_period };
+    let result = pic.update_call(canister_id, user, "create_deposit_intention", encode_args((args,)).unwrap())
+        .expect("Failed to create deposit intention");
+    let intention = decode_one::<Result<DepositIntention, StakingError>>(&result).unwrap()
+        .expect("Deposit intention should be created");
+
+    ledger_transfer(pic, user, AccountIdentifier::new(&canister_id, &Subaccount(intention.subaccount)), amount);
+
+    let result = pic.update_call(canister_id, user, "confirm_deposit", encode_args((intention.subaccount,)).unwrap())
+        .expect("Failed to confirm deposit");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Deposit should be confirmed: {:?}", response);
+
+    get_deposits(pic, canister_id, user).into_iter()
+        .find(|d| d.subaccount == intention.subaccount)
+        .expect("Confirmed deposit should be listed")
+}
+
+// Send `amount` from `from` to the pool's reward subaccount. Returns that account.
+fn fund_rewards(pic: &PocketIc, canister_id: Principal, from: Principal, amount: u64) -> AccountIdentifier {
+    let result = pic.query_call(canister_id, from, "get_reward_address", encode_args(()).unwrap())
+        .expect("Failed to query reward address");
+    let address: String = decode_one(&result).unwrap();
+    let reward_account = AccountIdentifier::from_hex(&address).expect("Reward address should be valid");
+    ledger_transfer(pic, from, reward_account, amount);
+    reward_account
 }
 
 fn setup() -> (PocketIc, Principal) {
@@ -170,10 +859,14 @@ fn test_reward_pool_empty() {
     
     match result {
         Ok(data) => {
-            let response: Result<u64, StakingError> = decode_one(&data)
+            let response: Result<RewardReport, StakingError> = decode_one(&data)
                 .expect("Failed to decode reward response");
             assert!(response.is_ok(), "Reward should succeed with no stakers");
-            assert_eq!(response.unwrap(), 0);
+            let report = response.unwrap();
+            assert_eq!(report.distributed, 0);
+            assert_eq!(report.fees, 0);
+            assert_eq!(report.carried_forward, 0);
+            assert!(report.allocations.is_empty());
         }
         Err(err) => {
             panic!("Unexpected error: {}", err.reject_message);
@@ -186,9 +879,15 @@ fn test_slash_pool_empty() {
     let (pic, canister_id) = setup();
     let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
     let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    configure_slash_signer(&pic, canister_id, user);
     
-    let slash_args = encode_args((1_000_000u64, receiver)).unwrap();
-    let result = pic.update_call(canister_id, user, "slash_pool", slash_args);
+    let slash_args = encode_args((ProposeSlashArgs {
+        amount: 1_000_000,
+        receiver,
+        justification: "Test slash".to_string(),
+        target: None,
+    },)).unwrap();
+    let result = pic.update_call(canister_id, user, "propose_slash", slash_args);
     
     match result {
         Ok(data) => {
@@ -532,9 +1231,15 @@ fn test_slash_pool_comprehensive() {
     let (pic, canister_id) = setup();
     let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
     let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    configure_slash_signer(&pic, canister_id, user);
     
-    let zero_slash_args = encode_args((0u64, receiver)).unwrap();
-    let result = pic.update_call(canister_id, user, "slash_pool", zero_slash_args);
+    let zero_slash_args = encode_args((ProposeSlashArgs {
+        amount: 0,
+        receiver,
+        justification: "Test slash".to_string(),
+        target: None,
+    },)).unwrap();
+    let result = pic.update_call(canister_id, user, "propose_slash", zero_slash_args);
     
     match result {
         Ok(data) => {
@@ -550,8 +1255,13 @@ fn test_slash_pool_comprehensive() {
         }
     }
     
-    let slash_args = encode_args((1_000_000u64, receiver)).unwrap();
-    let result = pic.update_call(canister_id, user, "slash_pool", slash_args);
+    let slash_args = encode_args((ProposeSlashArgs {
+        amount: 1_000_000,
+        receiver,
+        justification: "Test slash".to_string(),
+        target: None,
+    },)).unwrap();
+    let result = pic.update_call(canister_id, user, "propose_slash", slash_args);
     
     match result {
         Ok(data) => {
@@ -807,9 +1517,9 @@ fn test_reward_distribution_edge_cases() {
    
    match result {
        Ok(data) => {
-           let response: Result<u64, StakingError> = decode_one(&data).unwrap();
+           let response: Result<RewardReport, StakingError> = decode_one(&data).unwrap();
            assert!(response.is_ok(), "Empty pool reward should succeed");
-           assert_eq!(response.unwrap(), 0, "Should distribute 0 rewards");
+           assert_eq!(response.unwrap().distributed, 0, "Should distribute 0 rewards");
        }
        Err(err) => {
            panic!("Unexpected error in empty pool reward: {}", err.reject_message);
@@ -820,8 +1530,8 @@ fn test_reward_distribution_edge_cases() {
        let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap());
        match result {
            Ok(data) => {
-               let response: Result<u64, StakingError> = decode_one(&data).unwrap();
-               assert_eq!(response.unwrap(), 0);
+               let response: Result<RewardReport, StakingError> = decode_one(&data).unwrap();
+               assert_eq!(response.unwrap().distributed, 0);
            }
            Err(_) => panic!("Multiple reward calls should work"),
        }
@@ -834,6 +1544,7 @@ fn test_reward_distribution_edge_cases() {
 fn test_slash_pool_receiver_scenarios() {
    let (pic, canister_id) = setup();
    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+   configure_slash_signer(&pic, canister_id, user);
    
    let receivers = vec![
        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap(),
@@ -841,8 +1552,13 @@ fn test_slash_pool_receiver_scenarios() {
    ];
    
    for receiver in receivers {
-       let slash_args = encode_args((1_000_000u64, receiver)).unwrap();
-       let result = pic.update_call(canister_id, user, "slash_pool", slash_args);
+       let slash_args = encode_args((ProposeSlashArgs {
+           amount: 1_000_000,
+           receiver,
+           justification: "Test slash".to_string(),
+           target: None,
+       },)).unwrap();
+       let result = pic.update_call(canister_id, user, "propose_slash", slash_args);
        
        match result {
            Ok(data) => {
@@ -988,8 +1704,1787 @@ fn test_edge_case_empty_operations() {
    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
        .expect("reward_pool should work on empty state");
    
-   let response: Result<u64, StakingError> = decode_one(&result).unwrap();
-   assert_eq!(response.unwrap(), 0);
+   let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+   assert_eq!(response.unwrap().distributed, 0);
    
    println!(" Edge case empty operations test passed");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_transfer_position_errors() {
+    let (pic, canister_id) = setup();
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+
+    let args = TransferPositionArgs {
+        deposit_id: 0,
+        new_owner: user1,
+        require_acceptance: false,
+    };
+    let result = pic.update_call(canister_id, user1, "transfer_position", encode_args((args,)).unwrap())
+        .expect("Failed to call transfer_position");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InvalidRecipient) => {},
+        other => panic!("Expected InvalidRecipient for self transfer, got {:?}", other),
+    }
+
+    let args = TransferPositionArgs {
+        deposit_id: 0,
+        new_owner: user2,
+        require_acceptance: true,
+    };
+    let result = pic.update_call(canister_id, user1, "transfer_position", encode_args((args,)).unwrap())
+        .expect("Failed to call transfer_position");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::DepositNotFound) => {},
+        other => panic!("Expected DepositNotFound, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, user2, "accept_position", encode_args((0u64,)).unwrap())
+        .expect("Failed to call accept_position");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::DepositNotFound) => {},
+        other => panic!("Expected DepositNotFound, got {:?}", other),
+    }
+
+    let result = pic.query_call(canister_id, user1, "get_position_transfers", encode_args((user1,)).unwrap())
+        .expect("Failed to query position transfers");
+    let transfers: Vec<PositionTransfer> = decode_one(&result).unwrap();
+    assert_eq!(transfers.len(), 0, "No offer should be recorded for a missing deposit");
+
+    println!("Transfer position errors test passed");
+}
+
+#[test]
+fn test_split_and_merge_validation() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let cases: Vec<(u64, Vec<u64>, &str)> = vec![
+        (0, vec![], "InvalidAmount"),
+        (0, vec![10_000], "InvalidAmount"), // not above the ledger fee
+        (0, vec![1_000_000], "DepositNotFound"),
+    ];
+    for (deposit_id, amounts, expected) in cases {
+        let result = pic.update_call(canister_id, user, "split_position", encode_args((deposit_id, amounts)).unwrap())
+            .expect("Failed to call split_position");
+        let response: Result<SplitResult, StakingError> = decode_one(&result).unwrap();
+        let err = response.expect_err("Split should fail");
+        assert_eq!(format!("{:?}", err), expected);
+    }
+
+    let cases: Vec<(Vec<u64>, &str)> = vec![
+        (vec![0], "InvalidAmount"),
+        (vec![1, 1], "InvalidAmount"),
+        (vec![0, 1], "DepositNotFound"),
+    ];
+    for (deposit_ids, expected) in cases {
+        let result = pic.update_call(canister_id, user, "merge_positions", encode_args((deposit_ids,)).unwrap())
+            .expect("Failed to call merge_positions");
+        let response: Result<MergeResult, StakingError> = decode_one(&result).unwrap();
+        let err = response.expect_err("Merge should fail");
+        assert_eq!(format!("{:?}", err), expected);
+    }
+
+    println!("Split and merge validation test passed");
+}
+
+#[test]
+fn test_transfer_position_with_acceptance() {
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user1]);
+    let deposit = stake(&pic, canister_id, user1, 100_000_000, LockPeriod::Days90);
+
+    let args = TransferPositionArgs {
+        deposit_id: deposit.id,
+        new_owner: user2,
+        require_acceptance: true,
+    };
+    let result = pic.update_call(canister_id, user1, "transfer_position", encode_args((args,)).unwrap())
+        .expect("Failed to call transfer_position");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Failed to offer position: {:?}", response);
+
+    // Stays with the sender until accepted
+    assert_eq!(get_deposits(&pic, canister_id, user1).len(), 1);
+    let result = pic.query_call(canister_id, user2, "get_position_transfers", encode_args((user2,)).unwrap())
+        .expect("Failed to query position transfers");
+    let transfers: Vec<PositionTransfer> = decode_one(&result).unwrap();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].from, user1);
+
+    let result = pic.update_call(canister_id, user2, "accept_position", encode_args((deposit.id,)).unwrap())
+        .expect("Failed to call accept_position");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Failed to accept position: {:?}", response);
+
+    assert!(get_deposits(&pic, canister_id, user1).is_empty());
+    let received = get_deposits(&pic, canister_id, user2);
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].id, deposit.id);
+    assert_eq!(received[0].amount, deposit.amount);
+    assert_eq!(received[0].subaccount, deposit.subaccount);
+    assert_eq!(received[0].deposit_time, deposit.deposit_time);
+
+    // Without acceptance the position moves right away
+    let args = TransferPositionArgs {
+        deposit_id: deposit.id,
+        new_owner: user1,
+        require_acceptance: false,
+    };
+    let result = pic.update_call(canister_id, user2, "transfer_position", encode_args((args,)).unwrap())
+        .expect("Failed to call transfer_position");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Failed to transfer position: {:?}", response);
+    assert!(get_deposits(&pic, canister_id, user2).is_empty());
+    assert_eq!(get_deposits(&pic, canister_id, user1).len(), 1);
+
+    println!("Transfer position with acceptance test passed");
+}
+
+#[test]
+fn test_split_position() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days180);
+
+    let amounts = vec![20_000_000u64, 30_000_000];
+    let result = pic.update_call(canister_id, user, "split_position", encode_args((deposit.id, amounts.clone())).unwrap())
+        .expect("Failed to call split_position");
+    let response: Result<SplitResult, StakingError> = decode_one(&result).unwrap();
+    let outcome = response.expect("Split should succeed");
+    assert!(outcome.failed.is_none(), "No split transfer should fail: {:?}", outcome.failed);
+    let new_ids = outcome.new_deposit_ids;
+    assert_eq!(new_ids.len(), 2);
+
+    let deposits = get_deposits(&pic, canister_id, user);
+    assert_eq!(deposits.len(), 3);
+    let original = deposits.iter().find(|d| d.id == deposit.id).unwrap();
+    assert_eq!(original.amount, deposit.amount - 50_000_000 - 2 * LEDGER_FEE);
+    for (id, amount) in new_ids.iter().zip(&amounts) {
+        let split = deposits.iter().find(|d| d.id == *id).unwrap();
+        assert_eq!(split.amount, *amount);
+        assert_eq!(split.lock_period, deposit.lock_period);
+        assert_eq!(split.deposit_time, deposit.deposit_time);
+    }
+
+    // Every position is backed by its own subaccount
+    for d in &deposits {
+        let balance = ledger_balance(&pic, AccountIdentifier::new(&canister_id, &Subaccount(d.subaccount)));
+        assert_eq!(balance, d.amount, "Ledger balance of deposit {} should match its record", d.id);
+    }
+    let result = pic.query_call(canister_id, user, "get_total_staked", encode_args(()).unwrap())
+        .expect("Failed to query total staked");
+    let total: u64 = decode_one(&result).unwrap();
+    assert_eq!(total, deposits.iter().map(|d| d.amount).sum::<u64>());
+
+    println!("Split position test passed");
+}
+
+#[test]
+fn test_merge_positions() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let first = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(60));
+    let second = stake(&pic, canister_id, user, 50_000_000, LockPeriod::Days90);
+
+    let result = pic.update_call(canister_id, user, "merge_positions", encode_args((vec![first.id, second.id],)).unwrap())
+        .expect("Failed to call merge_positions");
+    let response: Result<MergeResult, StakingError> = decode_one(&result).unwrap();
+    let merge = response.expect("Merge should succeed");
+    assert_eq!(merge.deposit_id, second.id, "Positions should merge into the one that unlocks last");
+    assert_eq!(merge.merged_deposit_ids, vec![first.id]);
+    assert!(merge.failed.is_none());
+
+    let deposits = get_deposits(&pic, canister_id, user);
+    assert_eq!(deposits.len(), 1);
+    let merged = &deposits[0];
+    assert_eq!(merged.amount, first.amount + second.amount - LEDGER_FEE);
+    assert_eq!(merged.deposit_time, second.deposit_time);
+    let balance = ledger_balance(&pic, AccountIdentifier::new(&canister_id, &Subaccount(merged.subaccount)));
+    assert_eq!(balance, merged.amount);
+    assert_eq!(ledger_balance(&pic, AccountIdentifier::new(&canister_id, &Subaccount(first.subaccount))), 0);
+
+    println!("Merge positions test passed");
+}
+
+#[test]
+fn test_merge_positions_partial_failure() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let first = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    let second = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(60));
+    let target = stake(&pic, canister_id, user, 50_000_000, LockPeriod::Days90);
+
+    // Stop the ledger once the first position is merged, so the second transfer fails
+    let merge = pic.submit_call(canister_id, user, "merge_positions", encode_args((vec![first.id, second.id, target.id],)).unwrap())
+        .expect("Failed to submit merge_positions");
+    while get_deposits(&pic, canister_id, user).iter().any(|d| d.id == first.id) {
+        pic.tick();
+    }
+    pic.stop_canister(MAINNET_LEDGER_CANISTER_ID, None).expect("Failed to stop the ledger");
+
+    let result = pic.await_call(merge).expect("merge_positions should not trap");
+    let response: Result<MergeResult, StakingError> = decode_one(&result).unwrap();
+    let merge = response.expect("A partly completed merge should report what was merged");
+    assert_eq!(merge.deposit_id, target.id);
+    assert_eq!(merge.merged_deposit_ids, vec![first.id]);
+    assert!(matches!(merge.failed, Some(StakingError::TransferFailed(_))), "Expected a failed transfer, got {:?}", merge.failed);
+
+    pic.start_canister(MAINNET_LEDGER_CANISTER_ID, None).expect("Failed to restart the ledger");
+    let deposits = get_deposits(&pic, canister_id, user);
+    assert_eq!(deposits.len(), 2);
+    let merged = deposits.iter().find(|d| d.id == target.id).expect("Target should remain");
+    assert_eq!(merged.amount, target.amount + first.amount - LEDGER_FEE);
+    let untouched = deposits.iter().find(|d| d.id == second.id).expect("Unmerged position should remain");
+    assert_eq!(untouched.amount, second.amount);
+    assert_eq!(ledger_balance(&pic, AccountIdentifier::new(&canister_id, &Subaccount(second.subaccount))), second.amount);
+
+    // The unmerged position is released and can be merged again
+    let result = pic.update_call(canister_id, user, "merge_positions", encode_args((vec![second.id, target.id],)).unwrap())
+        .expect("Failed to call merge_positions");
+    let response: Result<MergeResult, StakingError> = decode_one(&result).unwrap();
+    assert_eq!(response.expect("Merge should succeed").merged_deposit_ids, vec![second.id]);
+
+    println!("Merge positions partial failure test passed");
+}
+
+#[test]
+fn test_concurrent_reward_distributions() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(60));
+
+    fund_rewards(&pic, canister_id, user, 10_000_000);
+
+    // Both calls are in flight before either completes
+    let first = pic.submit_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to submit reward_pool");
+    let second = pic.submit_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to submit reward_pool");
+    let outcomes: Vec<Result<RewardReport, StakingError>> = [first, second].into_iter()
+        .map(|message| decode_one(&pic.await_call(message).expect("reward_pool should not trap")).unwrap())
+        .collect();
+
+    let reports: Vec<&RewardReport> = outcomes.iter().filter_map(|o| o.as_ref().ok()).collect();
+    assert_eq!(reports.len(), 1, "Only one distribution should run, got {:?}", outcomes);
+    assert!(
+        outcomes.iter().any(|o| matches!(o, Err(StakingError::DistributionInProgress))),
+        "The other call should be turned away, got {:?}", outcomes
+    );
+
+    let deposits = get_deposits(&pic, canister_id, user);
+    assert!(reports[0].accrued > 0);
+    assert_eq!(deposits[0].rewards, reports[0].accrued, "Rewards should accrue once");
+
+    println!("Concurrent reward distributions test passed");
+}
+
+#[test]
+fn test_claims_and_distributions_exclude_each_other() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(60));
+    let reward_account = fund_rewards(&pic, canister_id, user, 10_000_000);
+
+    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    response.expect("Distribution should succeed");
+
+    // Fully vested, and a second epoch funded
+    pic.advance_time(Duration::from_secs(31 * 24 * 60 * 60));
+    fund_rewards(&pic, canister_id, user, 10_000_000);
+
+    let claim = pic.submit_call(canister_id, user, "claim_vested", encode_args(()).unwrap())
+        .expect("Failed to submit claim_vested");
+    let distribution = pic.submit_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to submit reward_pool");
+    let claim: Result<RewardClaim, StakingError> = decode_one(&pic.await_call(claim).expect("claim_vested should not trap")).unwrap();
+    let distribution: Result<RewardReport, StakingError> = decode_one(&pic.await_call(distribution).expect("reward_pool should not trap")).unwrap();
+    match (&claim, &distribution) {
+        (Ok(_), Err(StakingError::ClaimInProgress)) | (Err(StakingError::DistributionInProgress), Ok(_)) => {},
+        other => panic!("Expected one call to be turned away, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    response.expect("Distribution should succeed once the claim is done");
+
+    let reserved: u64 = get_deposits(&pic, canister_id, user).iter().map(|d| d.rewards).sum();
+    assert!(ledger_balance(&pic, reward_account) >= reserved, "The reward subaccount must cover what it owes");
+
+    println!("Claim and distribution exclusion test passed");
+}
+
+#[test]
+fn test_matured_positions_do_not_compound() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+
+    let set_auto_compound = |enabled: bool| {
+        let result = pic.update_call(canister_id, user, "set_auto_compound", encode_args((deposit.id, enabled)).unwrap())
+            .expect("Failed to call set_auto_compound");
+        decode_one::<Result<(), StakingError>>(&result).unwrap()
+    };
+    set_auto_compound(true).expect("A locked position may compound");
+
+    pic.advance_time(Duration::from_secs(91 * 24 * 60 * 60));
+    match set_auto_compound(true) {
+        Err(StakingError::InvalidArgument(_)) => {},
+        other => panic!("Expected a matured position to be refused, got {:?}", other),
+    }
+
+    fund_rewards(&pic, canister_id, user, 10_000_000);
+
+    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    let report = response.expect("Distribution should succeed");
+    assert_eq!(report.distributed, 0, "Nothing should be compounded into a matured position");
+    assert!(report.accrued > 0);
+
+    let deposits = get_deposits(&pic, canister_id, user);
+    assert_eq!(deposits[0].amount, deposit.amount);
+    assert_eq!(deposits[0].rewards, report.accrued, "Rewards should accrue and vest instead");
+
+    println!("Matured positions compounding test passed");
+}
+
+#[test]
+fn test_withdraw_respects_lock_period() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    let user_account = AccountIdentifier::new(&user, &DEFAULT_SUBACCOUNT);
+
+    let withdraw = || {
+        let result = pic.update_call(canister_id, user, "withdraw", encode_args((WithdrawArgs { deposit_index: 0 },)).unwrap())
+            .expect("Failed to call withdraw");
+        decode_one::<Result<u64, StakingError>>(&result).unwrap()
+    };
+
+    // Deposit time is in nanoseconds and the lock period in seconds
+    pic.advance_time(Duration::from_secs(89 * 24 * 60 * 60));
+    match withdraw() {
+        Err(StakingError::LockPeriodNotExpired) => {},
+        other => panic!("Expected LockPeriodNotExpired before the position unlocks, got {:?}", other),
+    }
+
+    pic.advance_time(Duration::from_secs(24 * 60 * 60));
+    let before = ledger_balance(&pic, user_account);
+    let received = withdraw().expect("Withdrawal should succeed once the position has unlocked");
+    assert_eq!(received, deposit.amount - LEDGER_FEE);
+    assert_eq!(ledger_balance(&pic, user_account), before + received);
+    assert!(get_deposits(&pic, canister_id, user).is_empty());
+
+    println!("Withdraw lock period test passed");
+}
+
+#[test]
+fn test_icrc7_empty_collection() {
+    let (pic, canister_id) = setup();
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+
+    let result = pic.query_call(canister_id, user1, "icrc7_total_supply", encode_args(()).unwrap())
+        .expect("Failed to query total supply");
+    let supply: candid::Nat = decode_one(&result).unwrap();
+    assert_eq!(supply, candid::Nat::from(0u64));
+
+    let result = pic.query_call(canister_id, user1, "icrc7_owner_of", encode_args((vec![candid::Nat::from(0u64)],)).unwrap())
+        .expect("Failed to query owner");
+    let owners: Vec<Option<Account>> = decode_one(&result).unwrap();
+    assert_eq!(owners, vec![None]);
+
+    let result = pic.query_call(canister_id, user1, "icrc10_supported_standards", encode_args(()).unwrap())
+        .expect("Failed to query supported standards");
+    let standards: Vec<SupportedStandard> = decode_one(&result).unwrap();
+    let icrc7 = standards.iter().find(|s| s.name == "ICRC-7").expect("ICRC-7 should be listed");
+    assert_eq!(icrc7.url, "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7");
+    let icrc10 = standards.iter().find(|s| s.name == "ICRC-10").expect("ICRC-10 should be listed");
+    assert_eq!(icrc10.url, "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10");
+
+    let transfer = TransferArg {
+        from_subaccount: None,
+        to: Account { owner: user2, subaccount: None },
+        token_id: candid::Nat::from(0u64),
+        memo: None,
+        created_at_time: None,
+    };
+    let result = pic.update_call(canister_id, user1, "icrc7_transfer", encode_args((vec![transfer],)).unwrap())
+        .expect("Failed to call icrc7_transfer");
+    let results: Vec<Option<Result<candid::Nat, TransferError>>> = decode_one(&result).unwrap();
+    assert_eq!(results.len(), 1);
+    match &results[0] {
+        Some(Err(TransferError::NonExistingTokenId)) => {},
+        other => panic!("Expected NonExistingTokenId, got {:?}", other),
+    }
+
+    println!("ICRC-7 empty collection test passed");
+}
+
+#[test]
+fn test_icrc7_transfer_deduplication() {
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user1]);
+    let deposit = stake(&pic, canister_id, user1, 100_000_000, LockPeriod::Days90);
+    let created_at_time = pic.get_time().as_nanos_since_unix_epoch();
+
+    let transfer = |to: Principal| {
+        let arg = TransferArg {
+            from_subaccount: None,
+            to: Account { owner: to, subaccount: None },
+            token_id: candid::Nat::from(deposit.id),
+            memo: None,
+            created_at_time: Some(created_at_time),
+        };
+        let result = pic.update_call(canister_id, user1, "icrc7_transfer", encode_args((vec![arg],)).unwrap())
+            .expect("Failed to call icrc7_transfer");
+        let mut results: Vec<Option<Result<candid::Nat, TransferError>>> = decode_one(&result).unwrap();
+        results.remove(0).expect("Every transfer should have a result")
+    };
+
+    let tx_index = transfer(user2).expect("First transfer should succeed");
+    match transfer(user2) {
+        Err(TransferError::Duplicate { duplicate_of }) => assert_eq!(duplicate_of, tx_index),
+        other => panic!("Expected Duplicate for a repeated transfer, got {:?}", other),
+    }
+    assert_eq!(get_deposits(&pic, canister_id, user2).len(), 1);
+
+    // Different arguments are a different transfer; user1 no longer owns the token
+    match transfer(Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()) {
+        Err(TransferError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized, got {:?}", other),
+    }
+
+    println!("ICRC-7 transfer deduplication test passed");
+}
+
+#[test]
+fn test_pause_and_unpause() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    // Canisters created by PocketIc without a sender are controlled by the anonymous principal
+    let controller = Principal::anonymous();
+
+    let result = pic.update_call(canister_id, user, "pause", encode_args((PauseScope::Deposits,)).unwrap())
+        .expect("Failed to call pause");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for non-admin, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, controller, "pause", encode_args((PauseScope::Deposits,)).unwrap())
+        .expect("Failed to call pause");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Controller should be able to pause: {:?}", response);
+
+    let result = pic.query_call(canister_id, user, "get_pause_status", encode_args(()).unwrap())
+        .expect("Failed to query pause status");
+    let status: PauseStatus = decode_one(&result).unwrap();
+    assert_eq!(status, PauseStatus { deposits: true, ..Default::default() });
+
+    let args = DepositArgs {
+        amount: 1_000_000,
+        lock_period: LockPeriod::Days90,
+    };
+    let result = pic.update_call(canister_id, user, "create_deposit_intention", encode_args((args,)).unwrap())
+        .expect("Failed to call create_deposit_intention");
+    let response: Result<DepositIntention, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Paused) => {},
+        other => panic!("Expected Paused, got {:?}", other),
+    }
+
+    // Maintenance mode blocks the remaining scopes too
+    pic.update_call(canister_id, controller, "pause", encode_args((PauseScope::All,)).unwrap())
+        .expect("Failed to call pause");
+    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Paused) => {},
+        other => panic!("Expected Paused, got {:?}", other),
+    }
+
+    pic.update_call(canister_id, controller, "unpause", encode_args((PauseScope::All,)).unwrap())
+        .expect("Failed to call unpause");
+    let result = pic.query_call(canister_id, user, "get_pause_status", encode_args(()).unwrap())
+        .expect("Failed to query pause status");
+    let status: PauseStatus = decode_one(&result).unwrap();
+    assert_eq!(status, PauseStatus::default());
+
+    println!("Pause and unpause test passed");
+}
+
+#[test]
+fn test_emergency_withdraw_requires_emergency_mode() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let controller = Principal::anonymous();
+
+    let result = pic.update_call(canister_id, user, "emergency_withdraw", encode_args((0u64,)).unwrap())
+        .expect("Failed to call emergency_withdraw");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::EmergencyModeInactive) => {},
+        other => panic!("Expected EmergencyModeInactive, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, user, "set_emergency_mode", encode_args((true,)).unwrap())
+        .expect("Failed to call set_emergency_mode");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for non-admin, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, controller, "set_emergency_mode", encode_args((true,)).unwrap())
+        .expect("Failed to call set_emergency_mode");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Controller should enable emergency mode: {:?}", response);
+
+    let result = pic.query_call(canister_id, user, "get_pause_status", encode_args(()).unwrap())
+        .expect("Failed to query pause status");
+    let status: PauseStatus = decode_one(&result).unwrap();
+    assert!(status.all, "Emergency mode should pause the pool");
+
+    let result = pic.update_call(canister_id, user, "emergency_withdraw", encode_args((0u64,)).unwrap())
+        .expect("Failed to call emergency_withdraw");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::DepositNotFound) => {},
+        other => panic!("Expected DepositNotFound, got {:?}", other),
+    }
+
+    println!("Emergency withdraw test passed");
+}
+
+#[test]
+fn test_slash_multisig_configuration() {
+    let (pic, canister_id) = setup();
+    let signer1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let signer2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let controller = Principal::anonymous();
+
+    let args = ProposeSlashArgs {
+        amount: 1_000_000,
+        receiver,
+        justification: "Operator misbehaviour".to_string(),
+        target: None,
+    };
+    let result = pic.update_call(canister_id, signer1, "propose_slash", encode_args((args,)).unwrap())
+        .expect("Failed to call propose_slash");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized before signers are configured, got {:?}", other),
+    }
+
+    let config = SlashConfig { signers: vec![signer1, signer2], threshold: 3, proposal_ttl: 3600 };
+    let result = pic.update_call(canister_id, controller, "queue_admin_action", encode_args((AdminAction::SetSlashConfig(config),)).unwrap())
+        .expect("Failed to call queue_admin_action");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InvalidArgument(_)) => {},
+        other => panic!("Expected InvalidArgument for threshold above signer count, got {:?}", other),
+    }
+
+    let config = SlashConfig { signers: vec![signer1, signer2], threshold: 2, proposal_ttl: 3600 };
+    let result = pic.update_call(canister_id, signer1, "queue_admin_action", encode_args((AdminAction::SetSlashConfig(config),)).unwrap())
+        .expect("Failed to call queue_admin_action");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for non-admin, got {:?}", other),
+    }
+
+    let config = SlashConfig { signers: vec![signer1, signer2], threshold: 2, proposal_ttl: 3600 };
+    let response = apply_admin_action(&pic, canister_id, AdminAction::SetSlashConfig(config));
+    assert!(response.is_ok(), "Controller should configure signers: {:?}", response);
+
+    let result = pic.query_call(canister_id, signer1, "get_slash_config", encode_args(()).unwrap())
+        .expect("Failed to query slash config");
+    let config: SlashConfig = decode_one(&result).unwrap();
+    assert_eq!(config.signers.len(), 2);
+    assert_eq!(config.threshold, 2);
+
+    let result = pic.update_call(canister_id, signer2, "approve_slash", encode_args((0u64,)).unwrap())
+        .expect("Failed to call approve_slash");
+    let response: Result<SlashProposalStatus, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::ProposalNotFound) => {},
+        other => panic!("Expected ProposalNotFound, got {:?}", other),
+    }
+
+    println!("Slash multisig configuration test passed");
+}
+
+#[test]
+fn test_queued_slash_expires_with_its_action() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    configure_slash_signer(&pic, canister_id, user);
+
+    let args = ProposeSlashArgs {
+        amount: 10_000_000,
+        receiver,
+        justification: "Operator misbehaviour".to_string(),
+        target: None,
+    };
+    let result = pic.update_call(canister_id, user, "propose_slash", encode_args((args,)).unwrap())
+        .expect("Failed to call propose_slash");
+    let proposal_id = decode_one::<Result<u64, StakingError>>(&result).unwrap().expect("Slash should be proposed");
+
+    let get_proposal = || {
+        let result = pic.query_call(canister_id, user, "get_slash_proposal", encode_args((proposal_id,)).unwrap())
+            .expect("Failed to query slash proposal");
+        decode_one::<Option<SlashProposal>>(&result).unwrap().expect("Proposal should exist")
+    };
+    let SlashProposalStatus::Queued { action_id } = get_proposal().status else {
+        panic!("Expected the approved slash to be queued");
+    };
+
+    // Past the delay and the 14 day grace period
+    pic.advance_time(Duration::from_secs(16 * 24 * 60 * 60 + 1));
+    assert!(matches!(get_proposal().status, SlashProposalStatus::Expired), "Expected Expired before any update");
+
+    let result = pic.update_call(canister_id, user, "execute_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to call execute_queued_action");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::ProposalExpired) => {},
+        other => panic!("Expected ProposalExpired, got {:?}", other),
+    }
+    assert!(matches!(get_proposal().status, SlashProposalStatus::Expired), "Expected the proposal to be marked expired");
+
+    println!("Queued slash expiry test passed");
+}
+
+#[test]
+fn test_timelock_queue_and_cancel() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let controller = Principal::anonymous();
+
+    let result = pic.update_call(canister_id, user, "queue_admin_action", encode_args((AdminAction::AddAdmin(user),)).unwrap())
+        .expect("Failed to call queue_admin_action");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for non-controller, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, controller, "queue_admin_action", encode_args((AdminAction::AddAdmin(user),)).unwrap())
+        .expect("Failed to call queue_admin_action");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    let action_id = response.expect("Controller should queue the action");
+
+    let result = pic.update_call(canister_id, controller, "execute_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to call execute_queued_action");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::TimelockNotExpired) => {},
+        other => panic!("Expected TimelockNotExpired, got {:?}", other),
+    }
+
+    let result = pic.query_call(canister_id, user, "get_queued_actions", encode_args(()).unwrap())
+        .expect("Failed to query queued actions");
+    let actions: Vec<QueuedAction> = decode_one(&result).unwrap();
+    assert_eq!(actions.len(), 1);
+    assert!(matches!(actions[0].status, QueuedActionStatus::Pending));
+    assert!(actions[0].executable_at > actions[0].queued_at);
+
+    let result = pic.update_call(canister_id, controller, "cancel_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to call cancel_queued_action");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Cancel should succeed during the delay: {:?}", response);
+
+    pic.advance_time(Duration::from_secs(2 * 24 * 60 * 60 + 1));
+    let result = pic.update_call(canister_id, controller, "execute_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to call execute_queued_action");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::ProposalNotOpen) => {},
+        other => panic!("Expected ProposalNotOpen for a cancelled action, got {:?}", other),
+    }
+
+    let result = pic.query_call(canister_id, user, "get_admins", encode_args(()).unwrap())
+        .expect("Failed to query admins");
+    let admins: Vec<Principal> = decode_one(&result).unwrap();
+    assert!(admins.is_empty(), "Cancelled action must not add an admin");
+
+    println!("Timelock queue and cancel test passed");
+}
+
+#[test]
+fn test_governance_parameters() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.query_call(canister_id, user, "get_pool_parameters", encode_args(()).unwrap())
+        .expect("Failed to query pool parameters");
+    let parameters: PoolParameters = decode_one(&result).unwrap();
+    assert_eq!(parameters.tier_multipliers, TierMultipliers { days90: 10_000, days180: 10_000, days360: 10_000 });
+    assert_eq!(parameters.reward_interval, 0);
+
+    let args = SubmitProposalArgs {
+        title: "Boost long locks".to_string(),
+        description: "Reward 360 day locks at 2x".to_string(),
+        change: ParameterChange::SetTierMultipliers(TierMultipliers { days90: 10_000, days180: 10_000, days360: 0 }),
+    };
+    let result = pic.update_call(canister_id, user, "submit_proposal", encode_args((args,)).unwrap())
+        .expect("Failed to call submit_proposal");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InvalidArgument(_)) => {},
+        other => panic!("Expected InvalidArgument for a zero multiplier, got {:?}", other),
+    }
+
+    let args = SubmitProposalArgs {
+        title: "Boost long locks".to_string(),
+        description: "Reward 360 day locks at 2x".to_string(),
+        change: ParameterChange::SetTierMultipliers(TierMultipliers { days90: 10_000, days180: 10_000, days360: 20_000 }),
+    };
+    let result = pic.update_call(canister_id, user, "submit_proposal", encode_args((args,)).unwrap())
+        .expect("Failed to call submit_proposal");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::NoVotingPower) => {},
+        other => panic!("Expected NoVotingPower without stake, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, user, "vote", encode_args((0u64, true)).unwrap())
+        .expect("Failed to call vote");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::ProposalNotFound) => {},
+        other => panic!("Expected ProposalNotFound, got {:?}", other),
+    }
+
+    // Admins can change parameters too, through the timelock
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetRewardInterval(24 * 60 * 60)));
+    assert!(response.is_ok(), "Parameter change should apply after the delay: {:?}", response);
+
+    let result = pic.query_call(canister_id, user, "get_pool_parameters", encode_args(()).unwrap())
+        .expect("Failed to query pool parameters");
+    let parameters: PoolParameters = decode_one(&result).unwrap();
+    assert_eq!(parameters.reward_interval, 24 * 60 * 60);
+
+    println!("Governance parameters test passed");
+}
+
+#[test]
+fn test_governance_change_waits_for_timelock() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    stake(&pic, canister_id, user, 200_000_000, LockPeriod::Days360);
+
+    let args = SubmitProposalArgs {
+        title: "Fund insurance".to_string(),
+        description: "Route 10% of rewards to the insurance fund".to_string(),
+        change: ParameterChange::SetInsuranceShare(1_000),
+    };
+    let result = pic.update_call(canister_id, user, "submit_proposal", encode_args((args,)).unwrap())
+        .expect("Failed to call submit_proposal");
+    let proposal_id = decode_one::<Result<u64, StakingError>>(&result).unwrap()
+        .expect("Proposal should be submitted");
+
+    let result = pic.update_call(canister_id, user, "vote", encode_args((proposal_id, true)).unwrap())
+        .expect("Failed to call vote");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Vote should be counted: {:?}", response);
+
+    let finalize = || {
+        let result = pic.update_call(canister_id, user, "finalize_proposal", encode_args((proposal_id,)).unwrap())
+            .expect("Failed to call finalize_proposal");
+        decode_one::<Result<ProposalStatus, StakingError>>(&result).unwrap().expect("Proposal should exist")
+    };
+    let insurance_bps = || {
+        let result = pic.query_call(canister_id, user, "get_pool_parameters", encode_args(()).unwrap())
+            .expect("Failed to query pool parameters");
+        decode_one::<PoolParameters>(&result).unwrap().insurance_bps
+    };
+    let execute = |action_id: u64| {
+        let result = pic.update_call(canister_id, user, "execute_queued_action", encode_args((action_id,)).unwrap())
+            .expect("Failed to call execute_queued_action");
+        decode_one::<Result<(), StakingError>>(&result).unwrap()
+    };
+
+    pic.advance_time(Duration::from_secs(3 * 24 * 60 * 60 + 1));
+    let action_id = match finalize() {
+        ProposalStatus::Queued { action_id } => action_id,
+        other => panic!("Expected the passed proposal to be queued, got {:?}", other),
+    };
+    assert_eq!(insurance_bps(), 0, "A passed proposal must not apply before the timelock delay");
+    match execute(action_id) {
+        Err(StakingError::TimelockNotExpired) => {},
+        other => panic!("Expected TimelockNotExpired, got {:?}", other),
+    }
+
+    pic.advance_time(Duration::from_secs(2 * 24 * 60 * 60 + 1));
+    assert!(execute(action_id).is_ok(), "Queued change should apply after the delay");
+    assert_eq!(insurance_bps(), 1_000);
+    assert_eq!(finalize(), ProposalStatus::Executed);
+
+    println!("Governance timelock test passed");
+}
+
+#[test]
+fn test_voting_power_queries() {
+    let (pic, canister_id) = setup();
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+
+    let result = pic.query_call(canister_id, user1, "get_voting_power", encode_args((user1, None::<u64>)).unwrap())
+        .expect("Failed to query voting power");
+    let power: u64 = decode_one(&result).unwrap();
+    assert_eq!(power, 0, "User without deposits has no voting power");
+
+    let result = pic.query_call(canister_id, user1, "get_voting_powers", encode_args((vec![user1, user2], Some(0u64))).unwrap())
+        .expect("Failed to query voting powers");
+    let powers: Vec<(Principal, u64)> = decode_one(&result).unwrap();
+    assert_eq!(powers, vec![(user1, 0), (user2, 0)]);
+
+    println!("Voting power queries test passed");
+}
+
+#[test]
+fn test_balance_snapshots() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.update_call(canister_id, user, "take_snapshot", encode_args(()).unwrap())
+        .expect("Failed to call take_snapshot");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for a non-admin, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, Principal::anonymous(), "take_snapshot", encode_args(()).unwrap())
+        .expect("Failed to call take_snapshot");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    assert_eq!(response.expect("Admin should be able to take a snapshot"), 0);
+
+    let result = pic.query_call(canister_id, user, "get_snapshot", encode_args((0u64, 0u64, 10u64)).unwrap())
+        .expect("Failed to query snapshot");
+    let page: Option<SnapshotPage> = decode_one(&result).unwrap();
+    let page = page.expect("Snapshot 0 should exist");
+    assert_eq!(page.info.total_staked, 0);
+    assert_eq!(page.info.holders, 0);
+    assert!(page.entries.is_empty());
+
+    let result = pic.query_call(canister_id, user, "get_snapshot_balance", encode_args((0u64, user)).unwrap())
+        .expect("Failed to query snapshot balance");
+    let entry: Option<SnapshotEntry> = decode_one(&result).unwrap();
+    let entry = entry.expect("Existing snapshot should report a balance");
+    assert_eq!(entry.staked, 0);
+    assert_eq!(entry.by_tier, TierBalances { days90: 0, days180: 0, days360: 0, other: 0 });
+
+    let result = pic.query_call(canister_id, user, "get_snapshot_balance", encode_args((1u64, user)).unwrap())
+        .expect("Failed to query snapshot balance");
+    let entry: Option<SnapshotEntry> = decode_one(&result).unwrap();
+    assert!(entry.is_none(), "Unknown snapshot should return None");
+
+    println!("Balance snapshots test passed");
+}
+
+#[test]
+fn test_event_log() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let deposit_args = DepositArgs {
+        amount: 100_000_000,
+        lock_period: LockPeriod::Days90,
+    };
+    let result = pic.update_call(canister_id, user, "create_deposit_intention", encode_args((deposit_args,)).unwrap())
+        .expect("Failed to create deposit intention");
+    let response: Result<DepositIntention, StakingError> = decode_one(&result).unwrap();
+    let intention = response.expect("Deposit intention should be created");
+
+    let result = pic.update_call(canister_id, Principal::anonymous(), "pause", encode_args((PauseScope::Rewards,)).unwrap())
+        .expect("Failed to call pause");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok());
+
+    let result = pic.query_call(canister_id, user, "get_events", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query events");
+    let events: Vec<Event> = decode_one(&result).unwrap();
+    assert_eq!(events.len(), 2, "Expected the intention and the pause, got {:?}", events);
+    assert_eq!(events[0].id, 0);
+    assert_eq!(events[0].principal, user);
+    match &events[0].kind {
+        EventKind::DepositIntentionCreated { subaccount, expected_amount, .. } => {
+            assert_eq!(*subaccount, intention.subaccount);
+            assert_eq!(*expected_amount, 100_000_000);
+        }
+        other => panic!("Expected DepositIntentionCreated, got {:?}", other),
+    }
+    match &events[1].kind {
+        EventKind::Paused { scope: PauseScope::Rewards } => {},
+        other => panic!("Expected Paused, got {:?}", other),
+    }
+
+    let result = pic.query_call(canister_id, user, "get_user_events", encode_args((user, 0u64, 10u64)).unwrap())
+        .expect("Failed to query user events");
+    let events: Vec<Event> = decode_one(&result).unwrap();
+    assert_eq!(events.len(), 1, "Only the intention concerns the user");
+
+    let result = pic.query_call(canister_id, user, "get_events", encode_args((1u64, 10u64)).unwrap())
+        .expect("Failed to query events");
+    let events: Vec<Event> = decode_one(&result).unwrap();
+    assert_eq!(events.len(), 1, "Offset should skip the first event");
+
+    println!("Event log test passed");
+}
+
+#[test]
+fn test_icrc3_block_log() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    for method in ["pause", "unpause"] {
+        let result = pic.update_call(canister_id, Principal::anonymous(), method, encode_args((PauseScope::Rewards,)).unwrap())
+            .expect("Failed to change pause status");
+        let response: Result<(), StakingError> = decode_one(&result).unwrap();
+        assert!(response.is_ok());
+    }
+
+    let args = vec![GetBlocksArgs { start: candid::Nat::from(0u64), length: candid::Nat::from(10u64) }];
+    let result = pic.query_call(canister_id, user, "icrc3_get_blocks", encode_args((args,)).unwrap())
+        .expect("Failed to query blocks");
+    let response: GetBlocksResult = decode_one(&result).unwrap();
+    assert_eq!(response.log_length, candid::Nat::from(2u64));
+    assert_eq!(response.blocks.len(), 2);
+    assert!(response.archived_blocks.is_empty());
+
+    let has_field = |block: &Value, name: &str| match block {
+        Value::Map(fields) => fields.iter().any(|(key, _)| key == name),
+        _ => false,
+    };
+    assert!(!has_field(&response.blocks[0].block, "phash"), "The first block has no parent");
+    assert!(has_field(&response.blocks[1].block, "phash"), "Later blocks chain to their parent");
+    assert!(has_field(&response.blocks[1].block, "btype"));
+
+    let result = pic.query_call(canister_id, user, "icrc3_get_tip_certificate", encode_args(()).unwrap())
+        .expect("Failed to query tip certificate");
+    let certificate: Option<DataCertificate> = decode_one(&result).unwrap();
+    let certificate = certificate.expect("Tip should be certified once blocks exist");
+    assert!(!certificate.hash_tree.is_empty());
+
+    let result = pic.query_call(canister_id, user, "icrc3_get_archives", encode_args((GetArchivesArgs { from: None },)).unwrap())
+        .expect("Failed to query archives");
+    let archives: Vec<ArchiveInfo> = decode_one(&result).unwrap();
+    assert!(archives.is_empty(), "Small logs are not archived");
+
+    let result = pic.update_call(canister_id, user, "set_archive_wasm", encode_args((vec![0u8; 8],)).unwrap())
+        .expect("Failed to call set_archive_wasm");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for a non-controller, got {:?}", other),
+    }
+
+    println!("ICRC-3 block log test passed");
+}
+
+#[test]
+fn test_icrc3_archived_blocks_are_trimmed() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let controller = Principal::anonymous();
+    pic.add_cycles(canister_id, 10_000_000_000_000);
+
+    let archive_wasm = std::fs::read(ARCHIVE_WASM_PATH).expect("Failed to read the archive wasm. Run 'cargo build --target wasm32-unknown-unknown --release' first");
+    let result = pic.update_call(canister_id, controller, "set_archive_wasm", encode_args((archive_wasm,)).unwrap())
+        .expect("Failed to call set_archive_wasm");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_ok(), "Controller should upload the archive wasm: {:?}", response);
+
+    // 2001 events in all, one more than the archiving threshold
+    for _ in 0..1000 {
+        for method in ["pause", "unpause"] {
+            let result = pic.update_call(canister_id, controller, method, encode_args((PauseScope::Rewards,)).unwrap())
+                .expect("Failed to change pause status");
+            let response: Result<(), StakingError> = decode_one(&result).unwrap();
+            assert!(response.is_ok());
+        }
+    }
+
+    let get_archives = || {
+        let result = pic.query_call(canister_id, user, "icrc3_get_archives", encode_args((GetArchivesArgs { from: None },)).unwrap())
+            .expect("Failed to query archives");
+        decode_one::<Vec<ArchiveInfo>>(&result).unwrap()
+    };
+    // All but the newest 1000 blocks are archived
+    for _ in 0..100 {
+        if get_archives().first().is_some_and(|archive| archive.end == 1000u64) {
+            break;
+        }
+        pic.tick();
+    }
+    let archives = get_archives();
+    assert_eq!(archives.len(), 1);
+    assert_eq!(archives[0].start, candid::Nat::from(0u64));
+    assert_eq!(archives[0].end, candid::Nat::from(1000u64));
+
+    let result = pic.query_call(canister_id, user, "get_event_count", encode_args(()).unwrap())
+        .expect("Failed to query event count");
+    let count: u64 = decode_one(&result).unwrap();
+    assert_eq!(count, 2001, "Trimming must not change event ids");
+
+    let result = pic.query_call(canister_id, user, "get_events", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query events");
+    let events: Vec<Event> = decode_one(&result).unwrap();
+    assert_eq!(events.first().map(|e| e.id), Some(1001), "Archived events should be dropped locally");
+
+    let args = vec![
+        GetBlocksArgs { start: candid::Nat::from(0u64), length: candid::Nat::from(10u64) },
+        GetBlocksArgs { start: candid::Nat::from(1001u64), length: candid::Nat::from(2u64) },
+    ];
+    let result = pic.query_call(canister_id, user, "icrc3_get_blocks", encode_args((args,)).unwrap())
+        .expect("Failed to query blocks");
+    let response: GetBlocksResult = decode_one(&result).unwrap();
+    assert_eq!(response.log_length, candid::Nat::from(2001u64));
+    let local: Vec<candid::Nat> = response.blocks.iter().map(|b| b.id.clone()).collect();
+    assert_eq!(local, vec![candid::Nat::from(1001u64), candid::Nat::from(1002u64)]);
+    assert_eq!(response.archived_blocks.len(), 1);
+    assert_eq!(response.archived_blocks[0].args.len(), 1);
+    assert_eq!(response.archived_blocks[0].args[0].start, candid::Nat::from(0u64));
+    assert_eq!(response.archived_blocks[0].args[0].length, candid::Nat::from(10u64));
+
+    println!("ICRC-3 archive trimming test passed");
+}
+
+#[test]
+fn test_metrics_endpoint() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        url: "/metrics".to_string(),
+        headers: vec![],
+        body: vec![],
+    };
+    let result = pic.query_call(canister_id, user, "http_request", encode_args((request,)).unwrap())
+        .expect("Failed to call http_request");
+    let response: HttpResponse = decode_one(&result).unwrap();
+    assert_eq!(response.status_code, 200);
+    let body = String::from_utf8(response.body).expect("Metrics should be UTF-8");
+    assert!(body.contains("# TYPE staking_total_staked_e8s gauge"));
+    assert!(body.contains("staking_total_staked_e8s 0"));
+    assert!(body.contains("staking_tier_staked_e8s{tier=\"90d\"} 0"));
+    assert!(body.contains("staking_failed_transfers_total 0"));
+    assert!(body.contains("staking_cycles_balance"));
+
+    let request = HttpRequest {
+        method: "GET".to_string(),
+        url: "/other".to_string(),
+        headers: vec![],
+        body: vec![],
+    };
+    let result = pic.query_call(canister_id, user, "http_request", encode_args((request,)).unwrap())
+        .expect("Failed to call http_request");
+    let response: HttpResponse = decode_one(&result).unwrap();
+    assert_eq!(response.status_code, 404);
+
+    println!("Metrics endpoint test passed");
+}
+
+#[test]
+fn test_certified_queries() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.query_call(canister_id, user, "get_certified_total_staked", encode_args(()).unwrap())
+        .expect("Failed to query certified total staked");
+    let response: Option<CertifiedTotalStaked> = decode_one(&result).unwrap();
+    let response = response.expect("Queries should carry a certificate");
+    assert_eq!(response.total_staked, 0);
+    assert!(!response.certificate.is_empty());
+    assert!(!response.witness.is_empty());
+
+    let result = pic.query_call(canister_id, user, "get_certified_deposits", encode_args((user,)).unwrap())
+        .expect("Failed to query certified deposits");
+    let response: Option<CertifiedDeposits> = decode_one(&result).unwrap();
+    let response = response.expect("Queries should carry a certificate");
+    assert!(response.deposits.is_empty());
+    assert!(!response.witness.is_empty(), "Absence of positions is proven too");
+
+    // Called as an update there is no certificate to return
+    let result = pic.update_call(canister_id, user, "get_certified_total_staked", encode_args(()).unwrap())
+        .expect("Failed to call get_certified_total_staked");
+    let response: Option<CertifiedTotalStaked> = decode_one(&result).unwrap();
+    assert!(response.is_none());
+
+    println!("Certified queries test passed");
+}
+
+#[test]
+fn test_reconcile_permissions_and_empty_pool() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.update_call(canister_id, user, "reconcile", encode_args((false,)).unwrap())
+        .expect("Failed to call reconcile");
+    let response: Result<ReconciliationReport, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::Unauthorized) => {},
+        other => panic!("Expected Unauthorized for a non-admin, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, Principal::anonymous(), "reconcile", encode_args((true,)).unwrap())
+        .expect("Failed to call reconcile");
+    let response: Result<ReconciliationReport, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InvalidArgument(_)) => {},
+        other => panic!("Expected InvalidArgument when correcting an unpaused pool, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, Principal::anonymous(), "reconcile", encode_args((false,)).unwrap())
+        .expect("Failed to call reconcile");
+    let response: Result<ReconciliationReport, StakingError> = decode_one(&result).unwrap();
+    let report = response.expect("Admin should be able to reconcile");
+    assert_eq!(report.total_staked, report.sum_of_deposits);
+    assert_eq!(report.deposits_checked, 0);
+    assert!(report.mismatches.is_empty());
+    assert!(report.orphaned.is_empty());
+    assert!(!report.corrected);
+
+    println!("Reconcile test passed");
+}
+
+#[test]
+fn test_reconcile_reserved_rewards_and_held_slashes() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let admin = Principal::anonymous();
+
+    // Subaccounts are handed out in order: insurance in init, then the deposit,
+    // the reward subaccount and the slash subaccount
+    stake(&pic, canister_id, user, 1_000_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(60));
+    let reward_account = fund_rewards(&pic, canister_id, user, 10_000_000);
+    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    assert!(response.expect("Distribution should succeed").accrued > 0);
+
+    configure_slash_signer(&pic, canister_id, user);
+    let args = ProposeSlashArgs {
+        amount: 10_000_000,
+        receiver,
+        justification: "Operator misbehaviour".to_string(),
+        target: None,
+    };
+    execute_slash(&pic, canister_id, user, args).expect("Slash should execute");
+
+    let mut subaccount = [0u8; 32];
+    subaccount[24..32].copy_from_slice(&3u64.to_be_bytes());
+    ledger_transfer(&pic, user, AccountIdentifier::new(&canister_id, &Subaccount(subaccount)), 50_000_000);
+
+    let result = pic.update_call(canister_id, admin, "reconcile", encode_args((false,)).unwrap())
+        .expect("Failed to call reconcile");
+    let response: Result<ReconciliationReport, StakingError> = decode_one(&result).unwrap();
+    let report = response.expect("Admin should be able to reconcile");
+    assert!(report.mismatches.is_empty(), "Unexpected mismatches: {:?}", report.mismatches);
+    assert!(report.orphaned.is_empty(), "The slash subaccount is in use, not orphaned: {:?}", report.orphaned);
+    assert_eq!(report.slash_balance, Some(50_000_000));
+    assert_eq!(report.slash_held, 0, "The slash was delivered in full");
+    assert_eq!(report.slash_shortfall, 0);
+
+    let reserved: u64 = get_deposits(&pic, canister_id, user).iter().map(|d| d.rewards).sum();
+    assert_eq!(report.reward_reserved, reserved);
+    assert_eq!(report.reward_balance, Some(ledger_balance(&pic, reward_account)));
+    assert_eq!(report.reward_shortfall, 0);
+
+    println!("Reconcile reserved rewards and held slashes test passed");
+}
+
+#[test]
+fn test_reconcile_finds_and_corrects_ledger_drift() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let admin = Principal::anonymous();
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+
+    // Funds sent straight to a deposit subaccount, and to an intention that then expired
+    let deposit_account = AccountIdentifier::new(&canister_id, &Subaccount(deposit.subaccount));
+    ledger_transfer(&pic, user, deposit_account, 50_000_000);
+    let args = DepositArgs { amount: 100_000_000, lock_period: LockPeriod::Days90 };
+    let result = pic.update_call(canister_id, user, "create_deposit_intention", encode_args((args,)).unwrap())
+        .expect("Failed to create deposit intention");
+    let intention = decode_one::<Result<DepositIntention, StakingError>>(&result).unwrap()
+        .expect("Deposit intention should be created");
+    ledger_transfer(&pic, user, AccountIdentifier::new(&canister_id, &Subaccount(intention.subaccount)), 20_000_000);
+    pic.advance_time(Duration::from_secs(16 * 60));
+    pic.update_call(canister_id, user, "cleanup_expired_deposits", encode_args(()).unwrap())
+        .expect("Failed to cleanup");
+
+    let reconcile = |correct: bool| {
+        let result = pic.update_call(canister_id, admin, "reconcile", encode_args((correct,)).unwrap())
+            .expect("Failed to call reconcile");
+        decode_one::<Result<ReconciliationReport, StakingError>>(&result).unwrap()
+    };
+
+    let report = reconcile(false).expect("Admin should be able to reconcile");
+    assert_eq!(report.deposits_checked, 1);
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].deposit_id, deposit.id);
+    assert_eq!(report.mismatches[0].recorded, 100_000_000);
+    assert_eq!(report.mismatches[0].ledger, 150_000_000);
+    assert_eq!(report.orphaned.len(), 1);
+    assert_eq!(report.orphaned[0].subaccount, intention.subaccount);
+    assert_eq!(report.orphaned[0].balance, 20_000_000);
+    assert!(!report.corrected);
+    assert_eq!(get_deposits(&pic, canister_id, user)[0].amount, 100_000_000, "Reporting alone must not change records");
+
+    for scope in [PauseScope::Rewards, PauseScope::Slashing] {
+        let result = pic.update_call(canister_id, admin, "pause", encode_args((scope,)).unwrap())
+            .expect("Failed to pause");
+        decode_one::<Result<(), StakingError>>(&result).unwrap().expect("Admin should pause");
+    }
+    let report = reconcile(true).expect("Correction should succeed while paused");
+    assert!(report.corrected);
+    assert_eq!(report.mismatches.len(), 1);
+
+    assert_eq!(get_deposits(&pic, canister_id, user)[0].amount, 150_000_000);
+    let result = pic.query_call(canister_id, user, "get_total_staked", encode_args(()).unwrap())
+        .expect("Failed to query total staked");
+    let total: u64 = decode_one(&result).unwrap();
+    assert_eq!(total, 150_000_000);
+
+    let report = reconcile(false).expect("Admin should be able to reconcile");
+    assert!(report.mismatches.is_empty(), "Records should match the ledger after correction");
+    assert_eq!(report.total_staked, report.sum_of_deposits);
+
+    println!("Reconcile drift test passed");
+}
+
+#[test]
+fn test_slash_history_empty() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.query_call(canister_id, user, "get_slash_history", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query slash history");
+    let history: Vec<SlashRecord> = decode_one(&result).unwrap();
+    assert!(history.is_empty());
+
+    let result = pic.query_call(canister_id, user, "get_deposit_slashes", encode_args((0u64,)).unwrap())
+        .expect("Failed to query deposit slashes");
+    let slashes: Vec<DepositSlash> = decode_one(&result).unwrap();
+    assert!(slashes.is_empty());
+
+    let result = pic.update_call(canister_id, user, "deliver_held_slash", encode_args((0u64,)).unwrap())
+        .expect("Failed to call deliver_held_slash");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    assert!(matches!(response, Err(StakingError::Unauthorized)), "Only controllers may deliver held funds");
+
+    let result = pic.update_call(canister_id, Principal::anonymous(), "deliver_held_slash", encode_args((0u64,)).unwrap())
+        .expect("Failed to call deliver_held_slash");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    assert!(matches!(response, Err(StakingError::InvalidArgument(_))), "There is no slash to deliver");
+
+    println!("Slash history test passed");
+}
+
+#[test]
+fn test_slash_records_history_and_deposit_losses() {
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user1, user2]);
+    let first = stake(&pic, canister_id, user1, 300_000_000, LockPeriod::Days90);
+    let second = stake(&pic, canister_id, user2, 100_000_000, LockPeriod::Days180);
+    configure_slash_signer(&pic, canister_id, user1);
+
+    let args = ProposeSlashArgs {
+        amount: 40_000_000,
+        receiver,
+        justification: "Validator downtime".to_string(),
+        target: None,
+    };
+    execute_slash(&pic, canister_id, user1, args).expect("Slash should execute");
+
+    let result = pic.query_call(canister_id, user1, "get_slash_history", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query slash history");
+    let history: Vec<SlashRecord> = decode_one(&result).unwrap();
+    assert_eq!(history.len(), 1);
+    let record = &history[0];
+    assert_eq!(record.id, 0);
+    assert_eq!(record.reason, "Validator downtime");
+    assert_eq!(record.receiver, receiver);
+    assert_eq!(record.held, 0, "The receiver should have been paid");
+    assert!(matches!(record.target, SlashTarget::All));
+    assert_eq!(record.deductions.len(), 2);
+    assert_eq!(record.amount, record.deductions.iter().map(|d| d.deducted).sum::<u64>());
+    // Deposits pay the amount plus one fee each and the fee to the receiver
+    assert_eq!(record.amount, 40_000_000 + 3 * LEDGER_FEE);
+    assert_eq!(ledger_balance(&pic, AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT)), 40_000_000);
+
+    for (owner, deposit) in [(user1, &first), (user2, &second)] {
+        let deduction = record.deductions.iter().find(|d| d.deposit_id == deposit.id).expect("Every deposit should be slashed");
+        assert_eq!(deduction.owner, owner);
+        assert!(matches!(deduction.outcome, SlashOutcome::Collected { .. }));
+
+        let result = pic.query_call(canister_id, owner, "get_deposit_slashes", encode_args((deposit.id,)).unwrap())
+            .expect("Failed to query deposit slashes");
+        let slashes: Vec<DepositSlash> = decode_one(&result).unwrap();
+        assert_eq!(slashes.len(), 1);
+        assert_eq!(slashes[0].slash_id, record.id);
+        assert_eq!(slashes[0].reason, "Validator downtime");
+        assert_eq!(slashes[0].deducted, deduction.deducted);
+        assert_eq!(slashes[0].fee, LEDGER_FEE);
+
+        let current = get_deposits(&pic, canister_id, owner)[0].clone();
+        assert_eq!(current.amount, deposit.amount - deduction.deducted);
+        let balance = ledger_balance(&pic, AccountIdentifier::new(&canister_id, &Subaccount(current.subaccount)));
+        assert_eq!(balance, current.amount);
+    }
+    let deducted = |id| record.deductions.iter().find(|d| d.deposit_id == id).unwrap().deducted;
+    assert!(deducted(first.id) > 2 * deducted(second.id), "Losses should be proportional to stake");
+
+    println!("Slash history with funded positions test passed");
+}
+
+#[test]
+fn test_cohort_slash_validation() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    configure_slash_signer(&pic, canister_id, user);
+
+    let propose = |target: SlashTarget| {
+        let args = encode_args((ProposeSlashArgs {
+            amount: 1_000_000,
+            receiver,
+            justification: "Operator misbehaviour".to_string(),
+            target: Some(target),
+        },)).unwrap();
+        let result = pic.update_call(canister_id, user, "propose_slash", args)
+            .expect("Failed to call propose_slash");
+        decode_one::<Result<u64, StakingError>>(&result).unwrap()
+    };
+
+    match propose(SlashTarget::Deposits(vec![])) {
+        Err(StakingError::InvalidArgument(_)) => {},
+        other => panic!("Expected InvalidArgument for an empty deposit list, got {:?}", other),
+    }
+    match propose(SlashTarget::DepositedBetween { from: 2, to: 1 }) {
+        Err(StakingError::InvalidArgument(_)) => {},
+        other => panic!("Expected InvalidArgument for an inverted window, got {:?}", other),
+    }
+    match propose(SlashTarget::Tier(LockPeriod::Days360)) {
+        Err(StakingError::InsufficientFunds) => {},
+        other => panic!("Expected InsufficientFunds for an empty cohort, got {:?}", other),
+    }
+    match propose(SlashTarget::Principals(vec![receiver])) {
+        Err(StakingError::InsufficientFunds) => {},
+        other => panic!("Expected InsufficientFunds for a principal without stake, got {:?}", other),
+    }
+
+    println!("Cohort slash validation test passed");
+}
+
+#[test]
+fn test_cohort_slash_hits_only_the_target() {
+    let user1 = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let user2 = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user1, user2]);
+    let short = stake(&pic, canister_id, user1, 100_000_000, LockPeriod::Days90);
+    let long = stake(&pic, canister_id, user1, 100_000_000, LockPeriod::Days180);
+    pic.advance_time(Duration::from_secs(60 * 60));
+    let late = stake(&pic, canister_id, user2, 100_000_000, LockPeriod::Days90);
+    configure_slash_signer(&pic, canister_id, user1);
+
+    let amounts = || -> Vec<(u64, u64)> {
+        let mut deposits = get_deposits(&pic, canister_id, user1);
+        deposits.extend(get_deposits(&pic, canister_id, user2));
+        let mut amounts: Vec<(u64, u64)> = deposits.iter().map(|d| (d.id, d.amount)).collect();
+        amounts.sort();
+        amounts
+    };
+    let slash = |target: SlashTarget| {
+        let args = ProposeSlashArgs {
+            amount: 10_000_000,
+            receiver,
+            justification: "Cohort penalty".to_string(),
+            target: Some(target),
+        };
+        execute_slash(&pic, canister_id, user1, args).expect("Slash should execute");
+    };
+
+    // Only the 180 day position is in the tier
+    slash(SlashTarget::Tier(LockPeriod::Days180));
+    let slashed = 10_000_000 + 2 * LEDGER_FEE;
+    assert_eq!(amounts(), vec![(short.id, short.amount), (long.id, long.amount - slashed), (late.id, late.amount)]);
+
+    // Only the position made in the window
+    slash(SlashTarget::DepositedBetween { from: late.deposit_time, to: late.deposit_time + 1 });
+    assert_eq!(amounts(), vec![(short.id, short.amount), (long.id, long.amount - slashed), (late.id, late.amount - slashed)]);
+
+    let result = pic.query_call(canister_id, user1, "get_slash_history", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query slash history");
+    let history: Vec<SlashRecord> = decode_one(&result).unwrap();
+    let slashed_ids: Vec<Vec<u64>> = history.iter()
+        .map(|record| record.deductions.iter().map(|d| d.deposit_id).collect())
+        .collect();
+    assert_eq!(slashed_ids, vec![vec![long.id], vec![late.id]]);
+    assert!(matches!(history[0].target, SlashTarget::Tier(LockPeriod::Days180)));
+
+    let result = pic.query_call(canister_id, user1, "get_deposit_slashes", encode_args((short.id,)).unwrap())
+        .expect("Failed to query deposit slashes");
+    let slashes: Vec<DepositSlash> = decode_one(&result).unwrap();
+    assert!(slashes.is_empty(), "Positions outside the cohorts should have no losses");
+
+    println!("Cohort slash test passed");
+}
+
+#[test]
+fn test_insurance_fund() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.query_call(canister_id, user, "get_insurance_fund", encode_args(()).unwrap())
+        .expect("Failed to query insurance fund");
+    let fund: InsuranceFund = decode_one(&result).unwrap();
+    assert_eq!(fund.address.len(), 64);
+    assert_eq!(fund.balance, None);
+    assert_eq!(fund.contributed, 0);
+    assert_eq!(fund.paid_out, 0);
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetInsuranceShare(10_000)));
+    assert!(response.is_err(), "Insurance share above the cap should be rejected");
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetInsuranceShare(1_000)));
+    assert!(response.is_ok(), "Failed to set insurance share: {:?}", response);
+    let result = pic.query_call(canister_id, user, "get_pool_parameters", encode_args(()).unwrap())
+        .expect("Failed to query pool parameters");
+    let parameters: PoolParameters = decode_one(&result).unwrap();
+    assert_eq!(parameters.insurance_bps, 1_000);
+
+    println!("Insurance fund test passed");
+}
+
+#[test]
+fn test_insurance_top_up_before_first_deposit() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+
+    let result = pic.query_call(canister_id, user, "get_insurance_fund", encode_args(()).unwrap())
+        .expect("Failed to query insurance fund");
+    let fund: InsuranceFund = decode_one(&result).unwrap();
+    let insurance_account = AccountIdentifier::from_hex(&fund.address).expect("Insurance address should be valid");
+    ledger_transfer(&pic, user, insurance_account, 500_000_000);
+
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    assert_eq!(deposit.amount, 100_000_000, "The top-up must not be credited to a deposit");
+    assert_ne!(AccountIdentifier::new(&canister_id, &Subaccount(deposit.subaccount)), insurance_account);
+
+    let result = pic.update_call(canister_id, user, "refresh_insurance_balance", encode_args(()).unwrap())
+        .expect("Failed to call refresh_insurance_balance");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    assert_eq!(response.expect("Balance should be readable"), 500_000_000);
+    assert_eq!(ledger_balance(&pic, insurance_account), 500_000_000);
+
+    let result = pic.query_call(canister_id, user, "get_insurance_fund", encode_args(()).unwrap())
+        .expect("Failed to query insurance fund");
+    let fund: InsuranceFund = decode_one(&result).unwrap();
+    assert_eq!(AccountIdentifier::from_hex(&fund.address).unwrap(), insurance_account, "The address must not change");
+
+    println!("Insurance top-up test passed");
+}
+
+#[test]
+fn test_insurance_covers_slash_beyond_exposed_stake() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+
+    let result = pic.query_call(canister_id, user, "get_insurance_fund", encode_args(()).unwrap())
+        .expect("Failed to query insurance fund");
+    let fund: InsuranceFund = decode_one(&result).unwrap();
+    let insurance_account = AccountIdentifier::from_hex(&fund.address).expect("Insurance address should be valid");
+    ledger_transfer(&pic, user, insurance_account, 500_000_000);
+    let result = pic.update_call(canister_id, user, "refresh_insurance_balance", encode_args(()).unwrap())
+        .expect("Failed to call refresh_insurance_balance");
+    decode_one::<Result<u64, StakingError>>(&result).unwrap().expect("Balance should be readable");
+
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    configure_slash_signer(&pic, canister_id, user);
+
+    // More than the exposed stake, but within what the fund can pay
+    let args = ProposeSlashArgs {
+        amount: 300_000_000,
+        receiver,
+        justification: "Operator misbehaviour".to_string(),
+        target: None,
+    };
+    execute_slash(&pic, canister_id, user, args).expect("The fund should cover the slash");
+
+    let result = pic.query_call(canister_id, user, "get_slash_history", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query slash history");
+    let history: Vec<SlashRecord> = decode_one(&result).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].from_insurance, 300_000_000);
+    assert_eq!(history[0].amount, 0, "No deposit should be slashed");
+    assert_eq!(get_deposits(&pic, canister_id, user)[0].amount, deposit.amount);
+    assert_eq!(ledger_balance(&pic, AccountIdentifier::new(&receiver, &DEFAULT_SUBACCOUNT)), 300_000_000);
+
+    println!("Insurance slash coverage test passed");
+}
+
+#[test]
+fn test_slash_skips_deposits_busy_on_the_ledger() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let receiver = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let busy = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    let free = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    configure_slash_signer(&pic, canister_id, user);
+
+    let args = ProposeSlashArgs {
+        amount: 10_000_000,
+        receiver,
+        justification: "Operator misbehaviour".to_string(),
+        target: None,
+    };
+    let result = pic.update_call(canister_id, user, "propose_slash", encode_args((args,)).unwrap())
+        .expect("Failed to call propose_slash");
+    decode_one::<Result<u64, StakingError>>(&result).unwrap().expect("Slash should be proposed");
+    let result = pic.query_call(canister_id, user, "get_queued_actions", encode_args(()).unwrap())
+        .expect("Failed to query queued actions");
+    let actions: Vec<QueuedAction> = decode_one(&result).unwrap();
+    let action_id = actions.last().expect("The slash should be queued").id;
+    pic.advance_time(Duration::from_secs(2 * 24 * 60 * 60 + 1));
+
+    // The split holds its deposit while it waits on the ledger
+    let split = pic.submit_call(canister_id, user, "split_position", encode_args((busy.id, vec![10_000_000u64])).unwrap())
+        .expect("Failed to submit split_position");
+    let slash = pic.submit_call(canister_id, user, "execute_queued_action", encode_args((action_id,)).unwrap())
+        .expect("Failed to submit execute_queued_action");
+    let split: Result<SplitResult, StakingError> = decode_one(&pic.await_call(split).expect("split_position should not trap")).unwrap();
+    let slash: Result<(), StakingError> = decode_one(&pic.await_call(slash).expect("execute_queued_action should not trap")).unwrap();
+    split.expect("Split should succeed");
+    slash.expect("Slash should succeed");
+
+    let result = pic.query_call(canister_id, user, "get_slash_history", encode_args((0u64, 10u64)).unwrap())
+        .expect("Failed to query slash history");
+    let history: Vec<SlashRecord> = decode_one(&result).unwrap();
+    let slashed: Vec<u64> = history[0].deductions.iter().map(|d| d.deposit_id).collect();
+    assert_eq!(slashed, vec![free.id], "Only the deposit that was not busy should be slashed");
+
+    let deposits = get_deposits(&pic, canister_id, user);
+    for deposit in &deposits {
+        let balance = ledger_balance(&pic, AccountIdentifier::new(&canister_id, &Subaccount(deposit.subaccount)));
+        assert_eq!(balance, deposit.amount, "Records must match the ledger");
+    }
+    let split_from = deposits.iter().find(|d| d.id == busy.id).unwrap();
+    assert_eq!(split_from.amount, busy.amount - 10_000_000 - LEDGER_FEE);
+
+    println!("Busy deposit slash test passed");
+}
+
+#[test]
+fn test_protocol_fee_configuration() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let treasury = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+
+    let get_fee = || {
+        let result = pic.query_call(canister_id, user, "get_protocol_fee", encode_args(()).unwrap())
+            .expect("Failed to query protocol fee");
+        decode_one::<ProtocolFee>(&result).unwrap()
+    };
+    let fee = get_fee();
+    assert_eq!(fee.fee_bps, 0);
+    assert_eq!(fee.treasury, None);
+    assert_eq!(fee.collected, 0);
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetProtocolFee(fee.max_fee_bps + 1)));
+    assert!(response.is_err(), "Protocol fee above the cap should be rejected");
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetProtocolFee(500)));
+    assert!(response.is_ok(), "Failed to set protocol fee: {:?}", response);
+
+    // Only a controller can redirect the fee
+    let result = pic.update_call(canister_id, user, "queue_admin_action", encode_args((AdminAction::SetTreasury(treasury),)).unwrap())
+        .expect("Failed to call queue_admin_action");
+    let response: Result<u64, StakingError> = decode_one(&result).unwrap();
+    assert!(response.is_err(), "Non-controller should not be able to set the treasury");
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::SetTreasury(treasury));
+    assert!(response.is_ok(), "Failed to set treasury: {:?}", response);
+
+    let fee = get_fee();
+    assert_eq!(fee.fee_bps, 500);
+    assert_eq!(fee.treasury, Some(treasury));
+
+    println!("Protocol fee configuration test passed");
+}
+
+#[test]
+fn test_claim_rewards_without_rewards() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let result = pic.update_call(canister_id, user, "claim_rewards", encode_args((None::<u64>,)).unwrap())
+        .expect("Failed to call claim_rewards");
+    let response: Result<RewardClaim, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InsufficientFunds) => {},
+        other => panic!("Expected InsufficientFunds with nothing to claim, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, user, "claim_rewards", encode_args((Some(42u64),)).unwrap())
+        .expect("Failed to call claim_rewards");
+    let response: Result<RewardClaim, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::DepositNotFound) => {},
+        other => panic!("Expected DepositNotFound for an unknown position, got {:?}", other),
+    }
+
+    let result = pic.update_call(canister_id, user, "set_auto_compound", encode_args((42u64, true)).unwrap())
+        .expect("Failed to call set_auto_compound");
+    let response: Result<(), StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::DepositNotFound) => {},
+        other => panic!("Expected DepositNotFound for an unknown position, got {:?}", other),
+    }
+
+    println!("Claim rewards test passed");
+}
+
+#[test]
+fn test_accrued_rewards_claimed_before_unlock() {
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[user]);
+    let deposit = stake(&pic, canister_id, user, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(60));
+    let reward_account = fund_rewards(&pic, canister_id, user, 10_000_000);
+
+    let result = pic.update_call(canister_id, user, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    let report = response.expect("Distribution should succeed");
+    assert!(report.accrued > LEDGER_FEE);
+    assert_eq!(report.distributed, 0, "Nothing should be compounded by default");
+    let accrued = get_deposits(&pic, canister_id, user)[0].rewards;
+    assert_eq!(accrued, report.accrued);
+
+    // Fully vested, while the position is still locked
+    pic.advance_time(Duration::from_secs(31 * 24 * 60 * 60));
+    let user_account = AccountIdentifier::new(&user, &DEFAULT_SUBACCOUNT);
+    let user_balance = ledger_balance(&pic, user_account);
+    let reward_balance = ledger_balance(&pic, reward_account);
+
+    let result = pic.update_call(canister_id, user, "claim_rewards", encode_args((Some(deposit.id),)).unwrap())
+        .expect("Failed to call claim_rewards");
+    let response: Result<RewardClaim, StakingError> = decode_one(&result).unwrap();
+    let claim = response.expect("Vested rewards should be claimable before unlock");
+    assert_eq!(claim.deposit_ids, vec![deposit.id]);
+    assert_eq!(claim.amount, accrued - LEDGER_FEE);
+    assert_eq!(claim.fee, LEDGER_FEE);
+
+    assert_eq!(ledger_balance(&pic, user_account), user_balance + claim.amount);
+    assert_eq!(ledger_balance(&pic, reward_account), reward_balance - accrued);
+    let current = &get_deposits(&pic, canister_id, user)[0];
+    assert_eq!(current.rewards, 0);
+    assert_eq!(current.amount, deposit.amount, "Claiming must not touch the locked principal");
+
+    let result = pic.update_call(canister_id, user, "claim_rewards", encode_args((Some(deposit.id),)).unwrap())
+        .expect("Failed to call claim_rewards");
+    let response: Result<RewardClaim, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InsufficientFunds) => {},
+        other => panic!("Expected InsufficientFunds after claiming everything, got {:?}", other),
+    }
+
+    println!("Accrue and claim test passed");
+}
+
+#[test]
+fn test_reward_vesting() {
+    let (pic, canister_id) = setup();
+    let user = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+
+    let get_vested = || {
+        let result = pic.query_call(canister_id, user, "get_vested_rewards", encode_args((user,)).unwrap())
+            .expect("Failed to query vested rewards");
+        decode_one::<VestedRewards>(&result).unwrap()
+    };
+    let vested = get_vested();
+    assert_eq!(vested.vested, 0);
+    assert_eq!(vested.unvested, 0);
+    assert_eq!(vested.vesting_period, 30 * 24 * 60 * 60);
+    assert!(vested.grants.is_empty());
+
+    let result = pic.update_call(canister_id, user, "claim_vested", encode_args(()).unwrap())
+        .expect("Failed to call claim_vested");
+    let response: Result<RewardClaim, StakingError> = decode_one(&result).unwrap();
+    match response {
+        Err(StakingError::InsufficientFunds) => {},
+        other => panic!("Expected InsufficientFunds with nothing vested, got {:?}", other),
+    }
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetRewardVesting(400 * 24 * 60 * 60)));
+    assert!(response.is_err(), "Vesting period above the cap should be rejected");
+
+    let response = apply_admin_action(&pic, canister_id, AdminAction::ChangeParameter(ParameterChange::SetRewardVesting(7 * 24 * 60 * 60)));
+    assert!(response.is_ok(), "Failed to set vesting period: {:?}", response);
+    assert_eq!(get_vested().vesting_period, 7 * 24 * 60 * 60);
+
+    println!("Reward vesting test passed");
+}
+
+#[test]
+fn test_rewards_weighted_by_stake_seconds() {
+    let early = Principal::from_text("xkbqi-2qaaa-aaaah-qbpqq-cai").unwrap();
+    let late = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
+    let (pic, canister_id) = setup_with_ledger(&[early, late]);
+
+    let early_deposit = stake(&pic, canister_id, early, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(300));
+    let late_deposit = stake(&pic, canister_id, late, 100_000_000, LockPeriod::Days90);
+    pic.advance_time(Duration::from_secs(100));
+    fund_rewards(&pic, canister_id, early, 10_000_000);
+
+    let now = pic.get_time().as_nanos_since_unix_epoch();
+    let result = pic.update_call(canister_id, early, "reward_pool", encode_args(()).unwrap())
+        .expect("Failed to call reward_pool");
+    let response: Result<RewardReport, StakingError> = decode_one(&result).unwrap();
+    response.expect("Distribution should succeed");
+
+    let early_rewards = get_deposits(&pic, canister_id, early)[0].rewards as f64;
+    let late_rewards = get_deposits(&pic, canister_id, late)[0].rewards as f64;
+    assert!(late_rewards > 0.0);
+
+    // Equal amounts, so shares follow the time each deposit was staked, about 4:1
+    let expected = (now - early_deposit.deposit_time) as f64 / (now - late_deposit.deposit_time) as f64;
+    let ratio = early_rewards / late_rewards;
+    assert!((ratio - expected).abs() < expected * 0.01, "Expected a ratio of {}, got {}", expected, ratio);
+
+    println!("Stake-seconds weighting test passed");
+}